@@ -0,0 +1,197 @@
+use std::fmt;
+use super::span::Span;
+
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum Severity {
+    Error,
+    Warning,
+    Note,
+}
+
+impl fmt::Display for Severity {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Severity::Error => write!(f, "error"),
+            Severity::Warning => write!(f, "warning"),
+            Severity::Note => write!(f, "note"),
+        }
+    }
+}
+
+/// A message about the program being compiled, optionally pointing at the
+/// region of source it's about.
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Diagnostic {
+    pub severity: Severity,
+    pub message: String,
+    pub span: Option<Span>,
+    /// Short text printed alongside the underline
+    pub label: Option<String>,
+    pub notes: Vec<String>,
+}
+
+impl Diagnostic {
+    pub fn new(severity: Severity, message: impl Into<String>) -> Diagnostic {
+        Diagnostic {
+            severity,
+            message: message.into(),
+            span: None,
+            label: None,
+            notes: vec![],
+        }
+    }
+
+    pub fn error(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Error, message)
+    }
+
+    pub fn warning(message: impl Into<String>) -> Diagnostic {
+        Diagnostic::new(Severity::Warning, message)
+    }
+
+    pub fn with_span(mut self, span: Span) -> Diagnostic {
+        self.span = Some(span);
+        self
+    }
+
+    pub fn with_label(mut self, label: impl Into<String>) -> Diagnostic {
+        self.label = Some(label.into());
+        self
+    }
+
+    pub fn with_note(mut self, note: impl Into<String>) -> Diagnostic {
+        self.notes.push(note.into());
+        self
+    }
+
+    /// Render the diagnostic the way a person would like to read it: the
+    /// message, where it happened, the offending line with the span
+    /// underlined, and then any notes.
+    ///
+    /// ```text
+    /// error: unexpected token
+    ///  --> example.bit:2:9
+    ///   |
+    /// 2 |     let = 5;
+    ///   |         ^
+    /// ```
+    pub fn render(&self, name: &str, source: &str) -> String {
+        let mut out = format!("{}: {}\n", self.severity, self.message);
+
+        let span = match self.span {
+            Some(span) => span,
+            None => {
+                for note in &self.notes {
+                    out += &format!("  = note: {}\n", note);
+                }
+                return out;
+            }
+        };
+
+        let start = span.start.min(source.len());
+        let line_start = source[..start].rfind('\n').map_or(0, |i| i + 1);
+        let line_end = source[start..].find('\n').map_or(source.len(), |i| start + i);
+        let line = source[line_start..line_end].trim_end_matches('\r');
+
+        // Keep any tabs from the original line so the underline stays aligned
+        let padding: String = source[line_start..start].chars()
+            .map(|c| if c == '\t' { '\t' } else { ' ' })
+            .collect();
+        // Spans running past the end of the line are only underlined up to it
+        let width = source[start..span.end.clamp(start, line_end)].chars().count().max(1);
+
+        let line_number = span.line.to_string();
+        let gutter = " ".repeat(line_number.len());
+
+        out += &format!("{}--> {}:{}\n", gutter, name, span);
+        out += &format!("{} |\n", gutter);
+        out += &format!("{} | {}\n", line_number, line);
+        out += &format!("{} | {}{}", gutter, padding, "^".repeat(width));
+        if let Some(label) = &self.label {
+            out += &format!(" {}", label);
+        }
+        out += "\n";
+
+        if !self.notes.is_empty() {
+            out += &format!("{} |\n", gutter);
+        }
+        for note in &self.notes {
+            out += &format!("{} = note: {}\n", gutter, note);
+        }
+
+        out
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn renders_snippet_with_carets() {
+        let source = "fn main() {\n    let = 5;\n}";
+        let diagnostic = Diagnostic::error("unexpected token")
+            .with_span(Span { start: 20, end: 21, line: 2, column: 9 })
+            .with_label("expected an identifier")
+            .with_note("variables are declared as `let name = value;`");
+
+        assert_eq!(
+            diagnostic.render("test.bit", source),
+            concat!(
+                "error: unexpected token\n",
+                " --> test.bit:2:9\n",
+                "  |\n",
+                "2 |     let = 5;\n",
+                "  |         ^ expected an identifier\n",
+                "  |\n",
+                "  = note: variables are declared as `let name = value;`\n",
+            )
+        );
+    }
+
+    #[test]
+    fn underlines_the_whole_span() {
+        let source = "\tprint(foo);";
+        let diagnostic = Diagnostic::warning("unused call")
+            .with_span(Span { start: 1, end: 11, line: 1, column: 2 });
+
+        assert_eq!(
+            diagnostic.render("test.bit", source),
+            concat!(
+                "warning: unused call\n",
+                " --> test.bit:1:2\n",
+                "  |\n",
+                "1 | \tprint(foo);\n",
+                "  | \t^^^^^^^^^^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn points_past_the_end_of_input() {
+        let source = "fn main() {";
+        let diagnostic = Diagnostic::error("unexpected end of input")
+            .with_span(Span { start: 11, end: 11, line: 1, column: 12 });
+
+        assert_eq!(
+            diagnostic.render("test.bit", source),
+            concat!(
+                "error: unexpected end of input\n",
+                " --> test.bit:1:12\n",
+                "  |\n",
+                "1 | fn main() {\n",
+                "  |            ^\n",
+            )
+        );
+    }
+
+    #[test]
+    fn renders_without_a_span() {
+        let diagnostic = Diagnostic::error("no `main` function").with_note("execution starts at `main`");
+
+        assert_eq!(
+            diagnostic.render("test.bit", ""),
+            "error: no `main` function\n  = note: execution starts at `main`\n"
+        );
+    }
+}
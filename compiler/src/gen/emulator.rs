@@ -0,0 +1,627 @@
+//! A tiny interpreter for the subset of 16 bit x86 assembly that the
+//! generator emits. It lets tests run generated programs and check what they
+//! actually compute rather than just how the assembly looks.
+//!
+//! Code and data don't share memory here: instructions are kept as a list and
+//! jump targets are indices into it, while data labels are laid out in memory
+//! just past the boot sector.
+
+use std::collections::HashMap;
+use super::size::{ split_operands, strip_comment };
+
+const ORIGIN: u16 = 0x7c00;
+const DATA_START: u16 = 0x7e00;
+const STEP_LIMIT: usize = 1_000_000;
+
+const REGISTERS: [&str; 8] = ["ax", "cx", "dx", "bx", "sp", "bp", "si", "di"];
+const BYTE_REGISTERS: [&str; 8] = ["al", "cl", "dl", "bl", "ah", "ch", "dh", "bh"];
+const SEGMENT_REGISTERS: [&str; 4] = ["es", "cs", "ss", "ds"];
+
+const AX: usize = 0;
+const CX: usize = 1;
+const DX: usize = 2;
+const SP: usize = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Size {
+    Byte,
+    Word,
+}
+
+#[derive(Debug, Clone)]
+enum Operand {
+    Register(usize),
+    ByteRegister(usize),
+    /// Segments are always zero here, so these only need to hold that
+    SegmentRegister,
+    Immediate(i32),
+    Memory {
+        size: Option<Size>,
+        registers: Vec<usize>,
+        displacement: i32,
+    },
+}
+
+#[derive(Debug, Clone)]
+struct Instruction {
+    mnemonic: String,
+    operands: Vec<Operand>,
+    line: String,
+}
+
+pub struct Machine {
+    registers: [u16; 8],
+    memory: Vec<u8>,
+    zero: bool,
+    sign: bool,
+    overflow: bool,
+    carry: bool,
+    output: String,
+    labels: HashMap<String, u16>,
+}
+
+fn parse_number(text: &str) -> Option<i32> {
+    let text = text.replace('_', "");
+    let (negative, text) = match text.strip_prefix('-') {
+        Some(rest) => (true, rest.to_string()),
+        None => (false, text),
+    };
+
+    let value = if let Some(hex) = text.strip_prefix("0x") {
+        i32::from_str_radix(hex, 16).ok()?
+    } else if let Some(binary) = text.strip_prefix("0b") {
+        i32::from_str_radix(binary, 2).ok()?
+    } else if text.len() == 3 && text.starts_with('\'') && text.ends_with('\'') {
+        text.chars().nth(1)? as i32
+    } else {
+        text.parse::<i32>().ok()?
+    };
+
+    Some(if negative { -value } else { value })
+}
+
+/// Split a sum such as `bp - 4` or `($$ + 510)` into its signed terms
+fn terms(text: &str) -> Vec<(i32, String)> {
+    let text: String = text.chars().filter(|c| !c.is_whitespace() && *c != '(' && *c != ')').collect();
+    let mut terms = vec![];
+    let mut term = String::new();
+    let mut sign = 1;
+
+    for c in text.chars() {
+        match c {
+            '+' | '-' if !term.is_empty() => {
+                terms.push((sign, term.clone()));
+                term.clear();
+                sign = if c == '-' { -1 } else { 1 };
+            },
+            '-' => sign = -sign,
+            '+' => (),
+            c => term.push(c),
+        }
+    }
+
+    if !term.is_empty() {
+        terms.push((sign, term));
+    }
+    terms
+}
+
+fn resolve(term: &str, labels: &HashMap<String, u16>, scope: &str) -> Result<i32, String> {
+    if term == "$$" {
+        Ok(ORIGIN as i32)
+    } else if let Some(number) = parse_number(term) {
+        Ok(number)
+    } else {
+        labels.get(&qualify(term, scope))
+            .map(|address| *address as i32)
+            .ok_or_else(|| format!("unknown label `{}`", term))
+    }
+}
+
+/// Evaluate a sum of numbers, labels and `$$`
+fn evaluate(text: &str, labels: &HashMap<String, u16>, scope: &str) -> Result<i32, String> {
+    terms(text).iter().try_fold(0, |total, (sign, term)| Ok(total + sign * resolve(term, labels, scope)?))
+}
+
+/// Local labels (starting with `.`) belong to the last non-local label
+fn qualify(label: &str, scope: &str) -> String {
+    if label.starts_with('.') {
+        format!("{}{}", scope, label)
+    } else {
+        label.to_string()
+    }
+}
+
+fn parse_operand(text: &str, labels: &HashMap<String, u16>, scope: &str) -> Result<Operand, String> {
+    if let Some(index) = REGISTERS.iter().position(|r| *r == text) {
+        return Ok(Operand::Register(index));
+    }
+    if let Some(index) = BYTE_REGISTERS.iter().position(|r| *r == text) {
+        return Ok(Operand::ByteRegister(index));
+    }
+    if SEGMENT_REGISTERS.contains(&text) {
+        return Ok(Operand::SegmentRegister);
+    }
+
+    let (size, rest) = if let Some(rest) = text.strip_prefix("byte ") {
+        (Some(Size::Byte), rest.trim())
+    } else if let Some(rest) = text.strip_prefix("word ") {
+        (Some(Size::Word), rest.trim())
+    } else {
+        (None, text)
+    };
+
+    if rest.starts_with('[') && rest.ends_with(']') {
+        let mut registers = vec![];
+        let mut displacement = 0;
+        for (sign, term) in terms(&rest[1..rest.len() - 1]) {
+            match REGISTERS.iter().position(|r| *r == term) {
+                Some(index) if sign == 1 => registers.push(index),
+                Some(_) => return Err(format!("registers can't be subtracted in `{}`", text)),
+                None => displacement += sign * resolve(&term, labels, scope)?,
+            }
+        }
+
+        return Ok(Operand::Memory { size, registers, displacement });
+    }
+
+    Ok(Operand::Immediate(evaluate(rest, labels, scope)?))
+}
+
+impl Machine {
+    /// Load `assembly` and run it from the top until it halts
+    pub fn run(assembly: &str) -> Result<Machine, String> {
+        let mut machine = Machine {
+            registers: [0; 8],
+            memory: vec![0; 0x10000],
+            zero: false,
+            sign: false,
+            overflow: false,
+            carry: false,
+            output: String::new(),
+            labels: HashMap::new(),
+        };
+
+        let program = machine.load(assembly)?;
+        machine.execute(&program)?;
+        Ok(machine)
+    }
+
+    /// Everything printed through the BIOS teletype service
+    pub fn output(&self) -> &str {
+        &self.output
+    }
+
+    /// Where the stack ended up once the program halted
+    pub fn stack_pointer(&self) -> u16 {
+        self.registers[SP]
+    }
+
+    /// The value of a global variable once the program has finished
+    pub fn global(&self, name: &str) -> i16 {
+        let address = self.labels[&format!("global_{}", name)];
+        self.read(address, Size::Word) as i16
+    }
+
+    fn load(&mut self, assembly: &str) -> Result<Vec<Instruction>, String> {
+        // First pass: find where every label points
+        let mut lines = vec![];
+        let mut scope = String::new();
+        let mut code_count = 0;
+        let mut data_address = DATA_START;
+
+        for line in assembly.lines() {
+            let line = strip_comment(line).trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let (label, rest) = match line.find(':') {
+                Some(i) if !line[..i].contains(' ') && !line[..i].contains('"') => (Some(&line[..i]), line[i + 1..].trim()),
+                _ => (None, line),
+            };
+
+            if let Some(label) = label {
+                if !label.starts_with('.') {
+                    scope = label.to_string();
+                }
+                let address = if rest.starts_with("db") || rest.starts_with("dw") { data_address } else { code_count };
+                self.labels.insert(qualify(label, &scope), address);
+            }
+
+            if rest.is_empty() || rest.starts_with("bits") || rest.starts_with("org") || rest.starts_with("times") {
+                continue;
+            }
+
+            let (mnemonic, operands) = match rest.find(' ') {
+                Some(i) => (&rest[..i], rest[i + 1..].trim()),
+                None => (rest, ""),
+            };
+
+            match mnemonic {
+                "db" | "dw" if label.is_none() => (),
+                "db" => {
+                    for operand in split_operands(operands) {
+                        if operand.starts_with('"') {
+                            for byte in operand[1..operand.len() - 1].bytes() {
+                                self.memory[data_address as usize] = byte;
+                                data_address += 1;
+                            }
+                        } else {
+                            let value = parse_number(&operand).ok_or_else(|| format!("bad byte `{}`", operand))?;
+                            self.memory[data_address as usize] = value as u8;
+                            data_address += 1;
+                        }
+                    }
+                },
+                "dw" => {
+                    for operand in split_operands(operands) {
+                        let value = parse_number(&operand).ok_or_else(|| format!("bad word `{}`", operand))?;
+                        self.write(data_address, value as u16, Size::Word);
+                        data_address += 2;
+                    }
+                },
+                _ => {
+                    lines.push((scope.clone(), mnemonic.to_string(), operands.to_string(), line.to_string()));
+                    code_count += 1;
+                },
+            }
+        }
+
+        // Second pass: now that labels are known, resolve operands
+        let mut program = vec![];
+        for (scope, mnemonic, operands, line) in lines {
+            let operands = split_operands(&operands).iter()
+                .map(|operand| parse_operand(operand, &self.labels, &scope))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|e| format!("{} in `{}`", e, line))?;
+            program.push(Instruction { mnemonic, operands, line });
+        }
+
+        Ok(program)
+    }
+
+    fn read(&self, address: u16, size: Size) -> u16 {
+        let low = self.memory[address as usize] as u16;
+        match size {
+            Size::Byte => low,
+            Size::Word => low | (self.memory[address.wrapping_add(1) as usize] as u16) << 8,
+        }
+    }
+
+    fn write(&mut self, address: u16, value: u16, size: Size) {
+        self.memory[address as usize] = value as u8;
+        if size == Size::Word {
+            self.memory[address.wrapping_add(1) as usize] = (value >> 8) as u8;
+        }
+    }
+
+    fn push(&mut self, value: u16) {
+        self.registers[SP] = self.registers[SP].wrapping_sub(2);
+        self.write(self.registers[SP], value, Size::Word);
+    }
+
+    fn pop(&mut self) -> u16 {
+        let value = self.read(self.registers[SP], Size::Word);
+        self.registers[SP] = self.registers[SP].wrapping_add(2);
+        value
+    }
+
+    fn address(&self, registers: &[usize], displacement: i32) -> u16 {
+        registers.iter().fold(displacement as u16, |address, r| address.wrapping_add(self.registers[*r]))
+    }
+
+    fn size_of(operands: &[Operand]) -> Size {
+        for operand in operands {
+            match operand {
+                Operand::Register(_) | Operand::SegmentRegister => return Size::Word,
+                Operand::ByteRegister(_) => return Size::Byte,
+                Operand::Memory { size: Some(size), .. } => return *size,
+                _ => (),
+            }
+        }
+        Size::Word
+    }
+
+    fn get(&self, operand: &Operand, size: Size) -> u16 {
+        match operand {
+            Operand::Register(r) => self.registers[*r],
+            Operand::ByteRegister(r) => {
+                let value = self.registers[r % 4];
+                if *r >= 4 { value >> 8 } else { value & 0xff }
+            },
+            Operand::SegmentRegister => 0,
+            Operand::Immediate(value) => *value as u16,
+            Operand::Memory { size: own, registers, displacement } => {
+                self.read(self.address(registers, *displacement), own.unwrap_or(size))
+            },
+        }
+    }
+
+    fn set(&mut self, operand: &Operand, value: u16, size: Size) -> Result<(), String> {
+        match operand {
+            Operand::Register(r) => self.registers[*r] = value,
+            Operand::ByteRegister(r) => {
+                let register = &mut self.registers[r % 4];
+                *register = if *r >= 4 {
+                    (*register & 0x00ff) | (value & 0xff) << 8
+                } else {
+                    (*register & 0xff00) | (value & 0xff)
+                };
+            },
+            Operand::Memory { size: own, registers, displacement } => {
+                let address = self.address(registers, *displacement);
+                self.write(address, value, own.unwrap_or(size));
+            },
+            Operand::SegmentRegister if value == 0 => (),
+            Operand::SegmentRegister => return Err("segments other than 0 aren't supported".to_string()),
+            Operand::Immediate(_) => return Err("cannot write to an immediate".to_string()),
+        }
+        Ok(())
+    }
+
+    fn set_result_flags(&mut self, result: u16, size: Size) {
+        let (mask, sign_bit) = match size {
+            Size::Byte => (0xff, 0x80),
+            Size::Word => (0xffff, 0x8000),
+        };
+        self.zero = result & mask == 0;
+        self.sign = result & sign_bit != 0;
+    }
+
+    fn subtract(&mut self, a: u16, b: u16, size: Size) -> u16 {
+        let result = a.wrapping_sub(b);
+        self.set_result_flags(result, size);
+        let sign_bit = if size == Size::Byte { 0x80 } else { 0x8000 };
+        self.carry = b > a;
+        self.overflow = (a ^ b) & (a ^ result) & sign_bit != 0;
+        result
+    }
+
+    fn condition(&self, suffix: &str) -> Result<bool, String> {
+        Ok(match suffix {
+            "e" | "z" => self.zero,
+            "ne" | "nz" => !self.zero,
+            "l" | "nge" => self.sign != self.overflow,
+            "ge" | "nl" => self.sign == self.overflow,
+            "le" | "ng" => self.zero || self.sign != self.overflow,
+            "g" | "nle" => !self.zero && self.sign == self.overflow,
+            "b" | "c" => self.carry,
+            "ae" | "nc" => !self.carry,
+            "be" => self.carry || self.zero,
+            "a" => !self.carry && !self.zero,
+            "s" => self.sign,
+            "ns" => !self.sign,
+            _ => return Err(format!("unknown condition `{}`", suffix)),
+        })
+    }
+
+    fn jump_target(operand: &Operand) -> Result<usize, String> {
+        match operand {
+            Operand::Immediate(index) => Ok(*index as usize),
+            _ => Err("jumps must go to labels".to_string()),
+        }
+    }
+
+    fn execute(&mut self, program: &[Instruction]) -> Result<(), String> {
+        let mut ip = 0;
+
+        for _ in 0..STEP_LIMIT {
+            let instruction = program.get(ip).ok_or("ran off the end of the program")?;
+            let operands = &instruction.operands;
+            let size = Machine::size_of(operands);
+            let error = |e: String| format!("{} in `{}`", e, instruction.line);
+            ip += 1;
+
+            match instruction.mnemonic.as_str() {
+                "mov" => {
+                    let value = self.get(&operands[1], size);
+                    self.set(&operands[0], value, size).map_err(error)?;
+                },
+                "movzx" => {
+                    let value = self.get(&operands[1], Size::Byte);
+                    self.set(&operands[0], value, Size::Word).map_err(error)?;
+                },
+                "xchg" => {
+                    let a = self.get(&operands[0], size);
+                    let b = self.get(&operands[1], size);
+                    self.set(&operands[0], b, size).map_err(error)?;
+                    self.set(&operands[1], a, size).map_err(error)?;
+                },
+                "push" => {
+                    let value = self.get(&operands[0], Size::Word);
+                    self.push(value);
+                },
+                "pop" => {
+                    let value = self.pop();
+                    self.set(&operands[0], value, Size::Word).map_err(error)?;
+                },
+                "add" | "sub" | "cmp" | "and" | "or" | "xor" | "test" => {
+                    let a = self.get(&operands[0], size);
+                    let b = self.get(&operands[1], size);
+                    let result = match instruction.mnemonic.as_str() {
+                        "add" => {
+                            let result = a.wrapping_add(b);
+                            let sign_bit = if size == Size::Byte { 0x80 } else { 0x8000 };
+                            self.set_result_flags(result, size);
+                            self.carry = result < a;
+                            self.overflow = !(a ^ b) & (a ^ result) & sign_bit != 0;
+                            result
+                        },
+                        "sub" | "cmp" => self.subtract(a, b, size),
+                        mnemonic => {
+                            let result = match mnemonic {
+                                "and" | "test" => a & b,
+                                "or" => a | b,
+                                _ => a ^ b,
+                            };
+                            self.set_result_flags(result, size);
+                            self.carry = false;
+                            self.overflow = false;
+                            result
+                        },
+                    };
+                    if instruction.mnemonic != "cmp" && instruction.mnemonic != "test" {
+                        self.set(&operands[0], result, size).map_err(error)?;
+                    }
+                },
+                "inc" | "dec" => {
+                    let a = self.get(&operands[0], size);
+                    let result = if instruction.mnemonic == "inc" { a.wrapping_add(1) } else { a.wrapping_sub(1) };
+                    self.set_result_flags(result, size);
+                    self.set(&operands[0], result, size).map_err(error)?;
+                },
+                "neg" => {
+                    let a = self.get(&operands[0], size);
+                    let result = self.subtract(0, a, size);
+                    self.set(&operands[0], result, size).map_err(error)?;
+                },
+                "not" => {
+                    let a = self.get(&operands[0], size);
+                    self.set(&operands[0], !a, size).map_err(error)?;
+                },
+                "shl" | "sal" | "shr" | "sar" => {
+                    let a = self.get(&operands[0], size);
+                    let count = (self.get(&operands[1], Size::Byte) & 0x1f) as u32;
+                    let result = match instruction.mnemonic.as_str() {
+                        "shl" | "sal" => a.checked_shl(count).unwrap_or(0),
+                        "shr" => a.checked_shr(count).unwrap_or(0),
+                        _ => ((a as i16) >> count.min(15)) as u16,
+                    };
+                    self.set_result_flags(result, size);
+                    self.set(&operands[0], result, size).map_err(error)?;
+                },
+                "imul" if operands.len() == 2 => {
+                    let a = self.get(&operands[0], Size::Word) as i16 as i32;
+                    let b = self.get(&operands[1], Size::Word) as i16 as i32;
+                    self.set(&operands[0], (a * b) as u16, Size::Word).map_err(error)?;
+                },
+                "imul" => {
+                    let a = self.registers[AX] as i16 as i32;
+                    let b = self.get(&operands[0], Size::Word) as i16 as i32;
+                    let result = a * b;
+                    self.registers[AX] = result as u16;
+                    self.registers[DX] = (result >> 16) as u16;
+                },
+                "cwd" => {
+                    self.registers[DX] = if (self.registers[AX] as i16) < 0 { 0xffff } else { 0 };
+                },
+                "idiv" => {
+                    let dividend = ((self.registers[DX] as u32) << 16 | self.registers[AX] as u32) as i32;
+                    let divisor = self.get(&operands[0], Size::Word) as i16 as i32;
+                    if divisor == 0 {
+                        return Err(error("division by zero".to_string()));
+                    }
+                    self.registers[AX] = (dividend / divisor) as u16;
+                    self.registers[DX] = (dividend % divisor) as u16;
+                },
+                "div" => {
+                    let dividend = (self.registers[DX] as u32) << 16 | self.registers[AX] as u32;
+                    let divisor = self.get(&operands[0], Size::Word) as u32;
+                    if divisor == 0 {
+                        return Err(error("division by zero".to_string()));
+                    }
+                    self.registers[AX] = (dividend / divisor) as u16;
+                    self.registers[DX] = (dividend % divisor) as u16;
+                },
+                "jmp" => ip = Machine::jump_target(&operands[0]).map_err(error)?,
+                "call" => {
+                    self.push(ip as u16);
+                    ip = Machine::jump_target(&operands[0]).map_err(error)?;
+                },
+                "ret" => {
+                    ip = self.pop() as usize;
+                    if let Some(operand) = operands.first() {
+                        let extra = self.get(operand, Size::Word);
+                        self.registers[SP] = self.registers[SP].wrapping_add(extra);
+                    }
+                },
+                "int" => match (self.get(&operands[0], Size::Byte), self.registers[AX] >> 8) {
+                    (0x10, 0x0e) => self.output.push((self.registers[AX] & 0xff) as u8 as char),
+                    // The whole image is loaded up front, so resetting the
+                    // disk and reading sectors from it always succeeds
+                    (0x13, 0x00 | 0x02) => {
+                        self.registers[AX] &= 0x00ff;
+                        self.carry = false;
+                    },
+                    (interrupt, function) => return Err(error(format!("unsupported interrupt {:#x}/{:#x}", interrupt, function))),
+                },
+                "cli" | "sti" => (),
+                "hlt" => return Ok(()),
+                "loop" => {
+                    self.registers[CX] = self.registers[CX].wrapping_sub(1);
+                    if self.registers[CX] != 0 {
+                        ip = Machine::jump_target(&operands[0]).map_err(error)?;
+                    }
+                },
+                mnemonic if mnemonic.starts_with("set") => {
+                    let value = self.condition(&mnemonic[3..]).map_err(error)? as u16;
+                    self.set(&operands[0], value, Size::Byte).map_err(error)?;
+                },
+                mnemonic if mnemonic.starts_with('j') => {
+                    if self.condition(&mnemonic[1..]).map_err(error)? {
+                        ip = Machine::jump_target(&operands[0]).map_err(error)?;
+                    }
+                },
+                _ => return Err(error("unsupported instruction".to_string())),
+            }
+        }
+
+        Err(format!("gave up after {} steps", STEP_LIMIT))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn runs_hand_written_assembly() {
+        let assembly = r#"
+            bits 16
+            org 0x7c00
+            mov bp, ($$ + 510)
+            mov sp, ($$ + 510)
+            mov ax, 7
+            push ax
+            mov ax, -3
+            mov bx, ax
+            pop ax
+            imul ax, bx
+            mov [global_result], ax
+            mov si, 0
+        .loop:
+            mov al, [message + si]
+            cmp al, 0
+            je .done
+            mov ah, 0x0e
+            int 0x10
+            add si, 1
+            jmp .loop
+        .done:
+            hlt
+        global_result: dw 0
+        message: db "Hi", 33, 0
+        "#;
+
+        let machine = Machine::run(assembly).unwrap();
+        assert_eq!(machine.global("result"), -21);
+        assert_eq!(machine.output(), "Hi!");
+    }
+
+    #[test]
+    fn signed_comparisons() {
+        let assembly = r#"
+            mov ax, -5
+            cmp ax, 3
+            setl bl
+            setg bh
+            mov [global_result], bx
+            hlt
+        global_result: dw 0
+        "#;
+
+        let machine = Machine::run(assembly).unwrap();
+        assert_eq!(machine.global("result"), 1);
+    }
+}
@@ -1,52 +1,255 @@
-use std::fs::File;
-use std::io::{ BufWriter, Write };
+use std::io;
+use std::io::Write;
 use super::parser::Program;
 use super::parser::function::Function;
-use super::parser::statement::Statement;
-use super::parser::expression::Expression;
+use super::parser::statement::{ Statement, StatementKind };
+use super::parser::expression::{ Expression, ExpressionKind };
+use super::diagnostics::Diagnostic;
+use super::sema::types::{ Type, type_of };
 use std::collections::HashMap;
 
-struct Context<'a> {
-    writer: &'a mut BufWriter<File>,
-    strings: HashMap<String, String>,
-    variables: HashMap<String, usize>, // stack offset
+mod size;
+#[cfg(test)]
+mod emulator;
+
+/// Bytes of the boot sector left for the program once the `0xaa55`
+/// signature takes the last two
+pub const BOOT_SECTOR_BUDGET: usize = 510;
+
+/// Undefined variables, and `return`, `break` or `continue` out of place, are
+/// reported by `sema`, which has to pass before code is generated
+const UNCHECKED: &str = "the program should have passed semantic analysis";
+const SECTOR_SIZE: usize = 512;
+/// Sectors the floppy loader reads: the rest of the first track, since not
+/// every BIOS can read across tracks in one go
+const LOADED_SECTORS: usize = 17;
+/// A 1.44MB floppy, which is what floppy images are padded to
+const FLOPPY_SIZE: usize = 1_474_560;
+
+/// How the program is laid out in the image NASM assembles
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum Layout {
+    /// Everything in the one sector the BIOS loads
+    #[default]
+    BootSector,
+    /// A floppy image whose boot sector loads the program from the sectors
+    /// following it, leaving room for bigger programs
+    Floppy,
+}
+
+impl Layout {
+    /// Bytes available to the compiled program
+    pub fn budget(&self) -> usize {
+        match self {
+            Layout::BootSector => BOOT_SECTOR_BUDGET,
+            Layout::Floppy => LOADED_SECTORS * SECTOR_SIZE,
+        }
+    }
+
+    fn describe(&self) -> &'static str {
+        match self {
+            Layout::BootSector => "boot sector",
+            Layout::Floppy => "floppy loader",
+        }
+    }
+}
+
+#[derive(Debug)]
+pub enum CodegenError {
+    /// An upper bound on the program's size, and what each part contributes
+    ProgramTooLarge {
+        layout: Layout,
+        size: usize,
+        sections: Vec<(String, usize)>,
+    },
+    Io(io::Error),
+}
+
+impl CodegenError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            CodegenError::Io(e) => Diagnostic::error(format!("unable to write assembly: {}", e)),
+            CodegenError::ProgramTooLarge { layout, size, sections } => {
+                let mut diagnostic = Diagnostic::error(format!("program is {} bytes, {} budget is {}", size, layout.describe(), layout.budget()));
+                for (name, size) in sections {
+                    diagnostic = diagnostic.with_note(format!("`{}` takes {} bytes", name, size));
+                }
+                diagnostic
+                    .with_note("sizes are upper bounds, counting every jump at its longest")
+                    .with_note("the stack lives below the program, so it isn't part of the budget")
+            },
+        }
+    }
+}
+
+/// Where `break` and `continue` inside a loop jump to
+struct LoopLabels {
+    break_label: String,
+    continue_label: String,
+    /// How many locals were on the stack when the loop body was entered
+    local_count: usize,
+}
+
+/// Where a local or parameter lives and how its value reads
+#[derive(Clone, Copy)]
+struct Variable {
+    /// Offset from bp: negative for locals and positive for parameters
+    offset: isize,
+    ty: Type,
+}
+
+struct Context<'a, W: Write> {
+    writer: &'a mut W,
+    layout: Layout,
+    /// The first error hit while writing. Once set, nothing else is written.
+    write_error: Option<io::Error>,
+    /// Interned string literals in order of first use; each one's label is
+    /// `string_` followed by its index, so the output is reproducible
+    strings: Vec<Vec<u8>>,
+    /// The variables of each block scope, innermost last. Empty at the top
+    /// level, where declarations make globals.
+    scopes: Vec<HashMap<String, Variable>>,
+    /// How many locals currently live on the stack
+    local_count: usize,
+    globals: Vec<(String, Type)>,
     label_counter: usize,
+    /// Label of the current function's epilogue, `None` outside functions
+    return_label: Option<String>,
+    /// The loops enclosing the code being compiled, innermost last
+    loops: Vec<LoopLabels>,
+    /// Each part of the program in order, with an upper bound on its size
+    sections: Vec<(String, usize)>,
 }
 
-impl Context<'_> {
-    pub fn new(writer: &mut BufWriter<File>) -> Context {
+impl<W: Write> Context<'_, W> {
+    pub fn new(writer: &mut W, layout: Layout) -> Context<'_, W> {
         Context {
             writer,
-            strings: HashMap::new(),
-            variables: HashMap::new(),
+            layout,
+            write_error: None,
+            strings: vec![],
+            scopes: vec![],
+            local_count: 0,
+            globals: vec![],
             label_counter: 0,
+            return_label: None,
+            loops: vec![],
+            sections: vec![],
         }
     }
 
     pub fn write(&mut self, data: &str) {
-        self.writer.write(data.as_bytes()).expect("Unable to write");
-        self.writer.write(&['\n' as u8]).expect("Unable to write");
+        if self.write_error.is_some() {
+            return;
+        }
+
+        if let Some((_, size)) = self.sections.last_mut() {
+            *size += data.lines().map(size::upper_bound).sum::<usize>();
+        }
+
+        let result = self.writer.write_all(data.as_bytes())
+            .and_then(|_| self.writer.write_all(b"\n"));
+        if let Err(e) = result {
+            self.write_error = Some(e);
+        }
+    }
+
+    /// Count everything written from now on towards the section `name`
+    pub fn start_section(&mut self, name: &str) {
+        self.sections.push((name.to_string(), 0));
     }
 
-    pub fn get_string(&mut self, data: &str) -> String {
-        let data = data.to_string();
+    pub fn get_string(&mut self, data: &[u8]) -> String {
+        let index = match self.strings.iter().position(|string| string == data) {
+            Some(index) => index,
+            None => {
+                self.strings.push(data.to_vec());
+                self.strings.len() - 1
+            },
+        };
 
-        if let Some(label) = self.strings.get(&data) {
-            label.clone()
+        format!("string_{}", index)
+    }
+
+    /// The memory operand (without brackets) holding the variable `name`.
+    /// Inner scopes shadow outer ones, and locals shadow globals.
+    pub fn get_variable_address(&self, name: &str) -> Option<String> {
+        if let Some(variable) = self.find_local(name) {
+            if variable.offset < 0 {
+                Some(format!("bp - {}", -variable.offset))
+            } else {
+                Some(format!("bp + {}", variable.offset))
+            }
+        } else if self.globals.iter().any(|(global, _)| global == name) {
+            Some(global_label(name))
         } else {
-            let label = format!("string_{}", self.strings.len());
-            self.strings.insert(data, label.clone());
-            label
+            None
+        }
+    }
+
+    fn find_local(&self, name: &str) -> Option<Variable> {
+        self.scopes.iter().rev().find_map(|scope| scope.get(name)).copied()
+    }
+
+    /// The type of `expression` given the variables currently in scope
+    pub fn type_of(&self, expression: &Expression) -> Type {
+        type_of(expression, &|name| match self.find_local(name) {
+            Some(variable) => variable.ty,
+            None => self.globals.iter()
+                .find(|(global, _)| global == name)
+                .map_or(Type::Signed, |(_, ty)| *ty),
+        })
+    }
+
+    /// Declare `name` in the innermost scope as the value just pushed
+    pub fn new_variable(&mut self, name: &str, ty: Type) {
+        self.local_count += 1;
+        let offset = -2 * self.local_count as isize;
+        self.scopes.last_mut()
+            .expect("locals can only be declared inside a scope")
+            .insert(name.to_string(), Variable { offset, ty });
+    }
+
+    /// Make the `index`th parameter of the current function visible as
+    /// `name`. Argument types aren't tracked across calls, so it's signed.
+    pub fn new_parameter(&mut self, name: &str, index: usize) {
+        // Skip the saved bp and the return address
+        self.scopes.last_mut()
+            .expect("parameters can only be declared inside a function")
+            .insert(name.to_string(), Variable { offset: 4 + 2 * index as isize, ty: Type::Signed });
+    }
+
+    pub fn enter_scope(&mut self) {
+        self.scopes.push(HashMap::new());
+    }
+
+    /// Jump to one of the innermost loop's labels, first popping any locals
+    /// declared inside the loop since the jump leaves their scopes early
+    pub fn write_loop_jump(&mut self, label: impl Fn(&LoopLabels) -> &String) {
+        let labels = self.loops.last().expect(UNCHECKED);
+        let (label, locals) = (label(labels).clone(), self.local_count - labels.local_count);
+
+        if locals > 0 {
+            self.write(&format!("add sp, {}", 2 * locals));
         }
+        self.write(&format!("jmp {}", label));
     }
 
-    pub fn get_variable_offset(&mut self, name: &str) -> Option<&usize> {
-        self.variables.get(name)
+    /// Leave the innermost scope, popping the locals declared in it
+    pub fn exit_scope(&mut self) {
+        let scope = self.scopes.pop().expect("exit_scope without a matching enter_scope");
+        let locals = scope.values().filter(|variable| variable.offset < 0).count();
+
+        if locals > 0 {
+            self.write(&format!("add sp, {}", 2 * locals));
+            self.local_count -= locals;
+        }
     }
 
-    pub fn new_variable(&mut self, name: &str) {
-        let length = self.variables.len();
-        self.variables.insert(name.to_string(), length);
+    pub fn new_global(&mut self, name: &str, ty: Type) {
+        if !self.globals.iter().any(|(global, _)| global == name) {
+            self.globals.push((name.to_string(), ty));
+        }
     }
 
     pub fn new_label(&mut self) -> String {
@@ -56,175 +259,1391 @@ impl Context<'_> {
     }
 
     pub fn new_function(&mut self) {
-        self.variables.clear();
+        // Parameters share a scope with the outermost block of the body,
+        // which `ret` cleans up after
+        self.scopes = vec![HashMap::new()];
+        self.local_count = 0;
         self.label_counter = 0;
+        self.return_label = Some(".return".to_string());
     }
 
-    pub fn write_strings(&mut self) {
-        let mut strings = vec![];
-        for (data, label) in &self.strings {
-            strings.push(format!("{}: db \"{}\", 0", label, data));
+    pub fn write_globals(&mut self) {
+        let globals: Vec<String> = self.globals.iter()
+            .map(|(name, _)| format!("{}: dw 0", global_label(name)))
+            .collect();
+
+        for g in globals {
+            self.write(&g);
         }
+    }
+
+    pub fn write_strings(&mut self) {
+        let strings: Vec<String> = self.strings.iter()
+            .enumerate()
+            .map(|(index, data)| format!("string_{}: db {}", index, string_operands(data)))
+            .collect();
 
-        // Multiple loops just to placate the borrow checker
         for s in strings {
             self.write(&s);
         }
     }
 }
 
-fn prologue(ctx: &mut Context) {
+fn global_label(name: &str) -> String {
+    format!("global_{}", name)
+}
+
+/// Functions get a prefix too, so no name can clash with the labels of the
+/// runtime (`prologue`, `epilogue`, `loader`), globals or strings
+fn function_label(name: &str) -> String {
+    format!("fn_{}", name)
+}
+
+/// Functions the prologue provides to every program, along with how many
+/// arguments each takes
+pub const BUILTIN_FUNCTIONS: &[(&str, usize)] = &[
+    ("print", 1),
+];
+
+fn prologue<W: Write>(ctx: &mut Context<W>, statements: &[Statement]) {
     ctx.write("bits 16");
     ctx.write("org 0x7c00");
 
+    if ctx.layout == Layout::Floppy {
+        loader(ctx);
+    }
+
+    ctx.start_section("prologue");
     ctx.write("prologue:");
 
-    // Setup the stack
-    ctx.write("mov bp, ($$ + 510)");
-    ctx.write("mov sp, ($$ + 510)");
-    ctx.write("call main");
+    // Globals, strings and the stack are all addressed from segment 0, but
+    // the BIOS only sets cs. The stack grows down from where the boot sector
+    // starts, so it can never run into the program.
+    ctx.write("cli");
+    ctx.write("xor ax, ax");
+    ctx.write("mov ds, ax");
+    ctx.write("mov es, ax");
+    ctx.write("mov ss, ax");
+    ctx.write("mov bp, $$");
+    ctx.write("mov sp, $$");
+    ctx.write("sti");
+
+    // Top level statements run, in order, before main. Every variable they
+    // declare becomes a global living in the data section.
+    for statement in statements {
+        compile_statement(ctx, statement);
+    }
+
+    ctx.write(&format!("call {}", function_label("main")));
     ctx.write("call epilogue");
 
     // Built in functions
+    ctx.start_section("print");
     ctx.write(r#"
-print:
+fn_print:
     push bp
     mov bp, sp
 
+    ; BH selects the display page, and could be anything after an expression
+    mov ax, [bp + 4]
     mov ah,0x0e
+    mov bh, 0
     int 0x10
 
     mov sp, bp
     pop bp
     ret
-    "#)
+    "#);
 }
 
-fn epilogue(ctx: &mut Context) {
+fn epilogue<W: Write>(ctx: &mut Context<W>) {
+    ctx.start_section("epilogue");
     ctx.write("epilogue:");
     ctx.write("cli");
     ctx.write("hlt");
 
+    ctx.start_section("globals");
+    ctx.write_globals();
+    ctx.start_section("strings");
     ctx.write_strings();
+}
+
+/// Fill the rest of the image once the program is written
+fn padding<W: Write>(ctx: &mut Context<W>) {
+    match ctx.layout {
+        Layout::BootSector => {
+            ctx.write("times 510 - ($-$$) db 0");
+            ctx.write("dw 0xaa55");
+        },
+        Layout::Floppy => ctx.write(&format!("times {} - ($-$$) db 0", FLOPPY_SIZE)),
+    }
+}
+
+/// The boot sector of a floppy image. It reads the sectors after itself,
+/// where the program is, to just past itself in memory and jumps there.
+fn loader<W: Write>(ctx: &mut Context<W>) {
+    ctx.write(&format!(r#"
+loader:
+    ; Segments could be anything, and es:bx is where sectors are read to
+    cli
+    xor ax, ax
+    mov ds, ax
+    mov es, ax
+    mov ss, ax
+    mov sp, $$
+    sti
 
-    ctx.write("times 510 - ($-$$) db 0");
-    ctx.write("dw 0xaa55");
+    ; dl still holds the drive the BIOS booted from. Floppy reads can fail
+    ; while the motor spins up, so reset the drive and try a few times.
+    mov si, 3
+.read:
+    mov ah, 0x02
+    mov al, {}
+    mov ch, 0
+    mov cl, 2
+    mov dh, 0
+    mov bx, prologue
+    int 0x13
+    jnc .loaded
+    mov ah, 0
+    int 0x13
+    dec si
+    jnz .read
+    cli
+    hlt
+.loaded:
+    jmp prologue
+
+times 510 - ($-$$) db 0
+dw 0xaa55
+    "#, LOADED_SECTORS));
+}
+
+/// The operands of a `db` holding `data` and its terminating zero. Printable
+/// characters go in quoted runs and everything else, including `"`, as
+/// numbers, so NASM reads the bytes back exactly
+fn string_operands(data: &[u8]) -> String {
+    let mut operands = vec![];
+    let mut run = String::new();
+
+    for &byte in data {
+        if (0x20..=0x7e).contains(&byte) && byte != b'"' {
+            run.push(byte as char);
+        } else {
+            if !run.is_empty() {
+                operands.push(format!("\"{}\"", run));
+                run.clear();
+            }
+            operands.push(byte.to_string());
+        }
+    }
+    if !run.is_empty() {
+        operands.push(format!("\"{}\"", run));
+    }
+    operands.push("0".to_string());
+
+    operands.join(", ")
 }
 
-pub fn generate(writer: &mut BufWriter<File>, program: Program) {
-    let mut ctx = Context::new(writer);
+/// Generate NASM assembly for `program` into any `Write` sink, whether that's
+/// a file, stdout or an in-memory buffer
+pub fn generate<W: Write>(writer: &mut W, program: Program, layout: Layout) -> Result<(), CodegenError> {
+    // Size the program with a dry run first, so nothing is written for one
+    // that can't fit. That's checked here rather than left to NASM, which
+    // only reports a negative `times` count.
+    let mut sink = io::sink();
+    let mut sizing = Context::new(&mut sink, layout);
+    compile_program(&mut sizing, &program);
+    let size = sizing.sections.iter().map(|(_, size)| size).sum();
+    if size > layout.budget() {
+        return Err(CodegenError::ProgramTooLarge { layout, size, sections: sizing.sections });
+    }
+
+    let mut ctx = Context::new(writer, layout);
+    compile_program(&mut ctx, &program);
+    padding(&mut ctx);
 
-    prologue(&mut ctx);
+    match ctx.write_error {
+        Some(e) => Err(CodegenError::Io(e)),
+        None => Ok(()),
+    }
+}
+
+fn compile_program<W: Write>(ctx: &mut Context<W>, program: &Program) {
+    prologue(ctx, &program.statements);
 
-    for function in program.functions {
-        compile_function(&mut ctx, &function);
+    for function in &program.functions {
+        ctx.start_section(&function.identifier);
+        compile_function(ctx, function);
     }
-    
-    epilogue(&mut ctx);
+
+    epilogue(ctx);
+}
+
+/// Generate the assembly for `program` as a `String`
+pub fn generate_string(program: Program, layout: Layout) -> Result<String, CodegenError> {
+    let mut assembly = vec![];
+    generate(&mut assembly, program, layout)?;
+    Ok(String::from_utf8(assembly).expect("Generated assembly should be valid UTF-8"))
 }
 
-fn compile_function(ctx: &mut Context, function: &Function) {
+/// Functions follow a cdecl-like calling convention:
+///
+/// - The caller pushes the arguments right to left, so the first one ends up
+///   nearest the return address, then `call`s the function
+/// - The callee saves bp and points it at the saved value, which puts the
+///   `n`th (0-based) parameter at `[bp + 4 + 2n]`. Locals live below bp.
+/// - The result is returned in `ax`. Any other register except `bp` and `sp`
+///   may be clobbered.
+/// - The caller removes the arguments from the stack once the call returns
+fn compile_function<W: Write>(ctx: &mut Context<W>, function: &Function) {
     ctx.new_function();
-    ctx.write(&format!("{}:", function.identifier));
+    ctx.write(&format!("{}:", function_label(&function.identifier)));
 
     // Normalize stack
     ctx.write("push bp");
     ctx.write("mov bp, sp");
 
-    if let Some(arg) = &function.argument {
-        ctx.write("push ax");
-        ctx.new_variable(arg);  
+    for (index, parameter) in function.parameters.iter().enumerate() {
+        ctx.new_parameter(&parameter.node, index);
     }
 
     for statement in &function.statements {
-        compile_statement(ctx, &statement);
+        compile_statement(ctx, statement);
     }
 
+    // Every `return` jumps here with its value already in ax
+    ctx.write(".return:");
     ctx.write("mov sp, bp");
     ctx.write("pop bp");
     ctx.write("ret");
 }
 
-fn compile_expression(ctx: &mut Context, expression: &Expression) {
-    match expression {
-        Expression::NumberLiteral(num) => {
+/// The operands of a comparison along with the condition code (the `cc` in
+/// `jcc` and `setcc`) under which it holds. Values are compared as unsigned
+/// if either operand is, and as signed otherwise.
+fn comparison<'a, W: Write>(ctx: &Context<W>, kind: &'a ExpressionKind) -> Option<(&'a Expression, &'a Expression, &'static str)> {
+    let (left, right, signed, unsigned) = match kind {
+        ExpressionKind::EqualComparison { left, right } => (left, right, "e", "e"),
+        ExpressionKind::NotComparison { left, right } => (left, right, "ne", "ne"),
+        ExpressionKind::LessThanComparison { left, right } => (left, right, "l", "b"),
+        ExpressionKind::LessThanOrEqualComparison { left, right } => (left, right, "le", "be"),
+        ExpressionKind::GreaterThanComparison { left, right } => (left, right, "g", "a"),
+        ExpressionKind::GreaterThanOrEqualComparison { left, right } => (left, right, "ge", "ae"),
+        _ => return None,
+    };
+
+    match ctx.type_of(left).common(ctx.type_of(right)) {
+        Type::Signed => Some((left, right, signed)),
+        Type::Unsigned => Some((left, right, unsigned)),
+    }
+}
+
+/// The condition code that holds exactly when `condition` doesn't
+fn inverse_condition(condition: &str) -> &'static str {
+    match condition {
+        "e" => "ne",
+        "ne" => "e",
+        "l" => "ge",
+        "ge" => "l",
+        "le" => "g",
+        "g" => "le",
+        "b" => "ae",
+        "ae" => "b",
+        "be" => "a",
+        "a" => "be",
+        _ => unreachable!("unknown condition code {}", condition),
+    }
+}
+
+/// Jump to `label` when `condition` is truthy if `jump_if` is set, or when
+/// it's false otherwise. Comparisons jump on the flags directly instead of
+/// materializing a boolean in `ax` first, and `&&` and `||` only evaluate
+/// their right hand side when the left one doesn't already decide the jump.
+fn compile_branch<W: Write>(ctx: &mut Context<W>, condition: &Expression, label: &str, jump_if: bool) {
+    if let Some((left, right, code)) = comparison(ctx, &condition.kind) {
+        compile_operands(ctx, left, right);
+        ctx.write("cmp ax, bx");
+        let code = if jump_if { code } else { inverse_condition(code) };
+        ctx.write(&format!("j{} {}", code, label));
+        return;
+    }
+
+    match &condition.kind {
+        ExpressionKind::LogicalNot { operand } => compile_branch(ctx, operand, label, !jump_if),
+        // `a && b` is false as soon as `a` is, and `a || b` true as soon as `a` is
+        ExpressionKind::LogicalAnd { left, right } if !jump_if => {
+            compile_branch(ctx, left, label, false);
+            compile_branch(ctx, right, label, false);
+        },
+        ExpressionKind::LogicalOr { left, right } if jump_if => {
+            compile_branch(ctx, left, label, true);
+            compile_branch(ctx, right, label, true);
+        },
+        // Otherwise the left side can only rule the jump out
+        ExpressionKind::LogicalAnd { left, right } | ExpressionKind::LogicalOr { left, right } => {
+            let skip_label = ctx.new_label();
+            compile_branch(ctx, left, &skip_label, !jump_if);
+            compile_branch(ctx, right, label, jump_if);
+            ctx.write(&format!("{}:", skip_label));
+        },
+        _ => {
+            compile_expression(ctx, condition);
+            ctx.write("cmp ax, 0");
+            ctx.write(&format!("{} {}", if jump_if { "jne" } else { "je" }, label));
+        },
+    }
+}
+
+fn compile_expression<W: Write>(ctx: &mut Context<W>, expression: &Expression) {
+    if let Some((left, right, code)) = comparison(ctx, &expression.kind) {
+        compile_operands(ctx, left, right);
+        ctx.write("cmp ax, bx");
+        ctx.write("mov ax, 0");
+        ctx.write(&format!("set{} al", code));
+        return;
+    }
+
+    match &expression.kind {
+        ExpressionKind::NumberLiteral(num) => {
             ctx.write(&format!("mov ax, {}", num));
         },
-        Expression::StringLiteral(data) => {
+        ExpressionKind::StringLiteral(data) => {
             let string = ctx.get_string(data);
             ctx.write(&format!("mov ax, {}", string));
         },
-        Expression::Variable(name) => {
-            let offset = *ctx.get_variable_offset(name).expect("Undefined variable");
-            ctx.write(&format!("mov ax, [bp - {}]", (2 * (offset + 1)) ))
-        }
-        Expression::Addition { left, right } => {
-            compile_expression(ctx, left);
-            ctx.write("mov bx, ax");
-            compile_expression(ctx, right);
-
+        ExpressionKind::Variable(name) => {
+            let address = ctx.get_variable_address(name).expect(UNCHECKED);
+            ctx.write(&format!("mov ax, [{}]", address))
+        },
+        ExpressionKind::FunctionCall { identifier, arguments } => compile_call(ctx, identifier, arguments),
+        ExpressionKind::Addition { left, right } => {
+            compile_operands(ctx, left, right);
             ctx.write("add ax, bx");
         },
-        Expression::Lookup { base, index } => {
-            compile_expression(ctx, base);
-            ctx.write("mov bx, ax");
-            compile_expression(ctx, index);
+        ExpressionKind::Subtraction { left, right } => {
+            compile_operands(ctx, left, right);
+            ctx.write("sub ax, bx");
+        },
+        ExpressionKind::Multiplication { left, right } => {
+            compile_operands(ctx, left, right);
+            // Only the low word of the result in dx:ax is kept
+            ctx.write("imul bx");
+        },
+        ExpressionKind::Division { left, right } => {
+            compile_operands(ctx, left, right);
+            compile_division(ctx, ctx.type_of(expression));
+        },
+        ExpressionKind::Remainder { left, right } => {
+            compile_operands(ctx, left, right);
+            // Signed remainders take the sign of the dividend, as in C
+            compile_division(ctx, ctx.type_of(expression));
+            ctx.write("mov ax, dx");
+        },
+        ExpressionKind::Negation { operand } => {
+            compile_expression(ctx, operand);
+            ctx.write("neg ax");
+        },
+        ExpressionKind::LogicalAnd { .. } | ExpressionKind::LogicalOr { .. } => {
+            let false_label = ctx.new_label();
+            let end_label = ctx.new_label();
+            compile_branch(ctx, expression, &false_label, false);
+            ctx.write("mov ax, 1");
+            ctx.write(&format!("jmp {}", end_label));
+            ctx.write(&format!("{}:", false_label));
+            ctx.write("mov ax, 0");
+            ctx.write(&format!("{}:", end_label));
+        },
+        ExpressionKind::LogicalNot { operand } => {
+            compile_expression(ctx, operand);
+            ctx.write("cmp ax, 0");
+            ctx.write("mov ax, 0");
+            ctx.write("sete al");
+        },
+        ExpressionKind::BitwiseAnd { left, right } => {
+            compile_operands(ctx, left, right);
+            ctx.write("and ax, bx");
+        },
+        ExpressionKind::BitwiseOr { left, right } => {
+            compile_operands(ctx, left, right);
+            ctx.write("or ax, bx");
+        },
+        ExpressionKind::BitwiseXor { left, right } => {
+            compile_operands(ctx, left, right);
+            ctx.write("xor ax, bx");
+        },
+        ExpressionKind::BitwiseNot { operand } => {
+            compile_expression(ctx, operand);
+            ctx.write("not ax");
+        },
+        ExpressionKind::ShiftLeft { left, right } => {
+            compile_operands(ctx, left, right);
+            ctx.write("mov cx, bx");
+            ctx.write("shl ax, cl");
+        },
+        ExpressionKind::ShiftRight { left, right } => {
+            compile_operands(ctx, left, right);
+            // Signed values keep their sign bit
+            ctx.write("mov cx, bx");
+            match ctx.type_of(left) {
+                Type::Signed => ctx.write("sar ax, cl"),
+                Type::Unsigned => ctx.write("shr ax, cl"),
+            }
+        },
+        ExpressionKind::Lookup { base, index } => {
+            compile_operands(ctx, index, base);
 
-            // Lookups are only available for single bits
+            // Lookups are only available for single bytes
             // Scale not available with 16 bit registers
-            ctx.write("mov al, [ebx + eax]");
+            ctx.write("mov si, ax");
+            ctx.write("mov al, [bx + si]");
+            ctx.write("mov ah, 0");
         },
-        Expression::NotComparison { left, right } => {
-            compile_expression(ctx, left);
-            ctx.write("mov bx, ax");
-            compile_expression(ctx, right);
+        ExpressionKind::NotComparison { .. }
+        | ExpressionKind::EqualComparison { .. }
+        | ExpressionKind::LessThanComparison { .. }
+        | ExpressionKind::LessThanOrEqualComparison { .. }
+        | ExpressionKind::GreaterThanComparison { .. }
+        | ExpressionKind::GreaterThanOrEqualComparison { .. } => unreachable!("comparisons are compiled above"),
+    }
+}
 
-            ctx.write("cmp ax, bx");
-            ctx.write("mov ax, 0");
-            ctx.write("setnz al");
-        }
+/// Divide `ax` by `bx` as `ty`, leaving the quotient in `ax` and the
+/// remainder in `dx`
+fn compile_division<W: Write>(ctx: &mut Context<W>, ty: Type) {
+    match ty {
+        Type::Signed => {
+            // Sign extend ax into dx:ax
+            ctx.write("cwd");
+            ctx.write("idiv bx");
+        },
+        Type::Unsigned => {
+            ctx.write("xor dx, dx");
+            ctx.write("div bx");
+        },
+    }
+}
+
+/// Evaluate the operands of a binary operation, leaving `left` in `ax` and
+/// `right` in `bx`. The left value waits on the stack while the right side
+/// is evaluated, as there's no telling which registers that will clobber.
+fn compile_operands<W: Write>(ctx: &mut Context<W>, left: &Expression, right: &Expression) {
+    compile_expression(ctx, left);
+    ctx.write("push ax");
+    compile_expression(ctx, right);
+    ctx.write("mov bx, ax");
+    ctx.write("pop ax");
+}
+
+/// Call `identifier` as described on `compile_function`, leaving whatever it
+/// returns in `ax`
+fn compile_call<W: Write>(ctx: &mut Context<W>, identifier: &str, arguments: &[Expression]) {
+    for argument in arguments.iter().rev() {
+        compile_expression(ctx, argument);
+        ctx.write("push ax");
+    }
+
+    ctx.write(&format!("call {}", function_label(identifier)));
+
+    if !arguments.is_empty() {
+        ctx.write(&format!("add sp, {}", 2 * arguments.len()));
+    }
+}
+
+/// Compile `statements` in a scope of their own, reclaiming the stack space
+/// of any locals they declare once the block finishes
+fn compile_block<W: Write>(ctx: &mut Context<W>, statements: &[Statement]) {
+    ctx.enter_scope();
+    for statement in statements {
+        compile_statement(ctx, statement);
     }
+    ctx.exit_scope();
+}
+
+/// Compile the body of a loop, with `break` jumping to `break_label` and
+/// `continue` to `continue_label`
+fn compile_loop_body<W: Write>(ctx: &mut Context<W>, statements: &[Statement], break_label: &str, continue_label: &str) {
+    ctx.loops.push(LoopLabels {
+        break_label: break_label.to_string(),
+        continue_label: continue_label.to_string(),
+        local_count: ctx.local_count,
+    });
+    compile_block(ctx, statements);
+    ctx.loops.pop();
 }
 
-fn compile_statement(ctx: &mut Context, statement: &Statement) {
-    match statement {
-        Statement::Assignment { identifier, value } => {
+fn compile_statement<W: Write>(ctx: &mut Context<W>, statement: &Statement) {
+    match &statement.kind {
+        StatementKind::Declaration { identifier, value } => {
             compile_expression(ctx, value);
 
-            match ctx.get_variable_offset(identifier) {
-                Some(offset) => {
-                    let offset = *offset;
-                    ctx.write(&format!("mov [bp - {}], ax", (2 * (offset + 1)) ));
-                },
-                None => {
-                    ctx.write("push ax");
-                    ctx.new_variable(identifier);        
-                }
+            // Variables keep the type of the value they're declared with
+            let ty = ctx.type_of(value);
+            if ctx.scopes.is_empty() {
+                ctx.new_global(identifier, ty);
+                ctx.write(&format!("mov [{}], ax", global_label(identifier)));
+            } else {
+                ctx.write("push ax");
+                ctx.new_variable(identifier, ty);
             }
         },
-        Statement::FunctionCall { identifier, param } => {
-            // Only handle a max of one param for now
-            if let Some(e) = param {
-                compile_expression(ctx, e);
-            }
+        StatementKind::Assignment { identifier, value } => {
+            compile_expression(ctx, value);
 
-            ctx.write(&format!("call {}", identifier));
+            let address = ctx.get_variable_address(identifier).expect(UNCHECKED);
+            ctx.write(&format!("mov [{}], ax", address));
         },
-        Statement::While { condition, statements } => {
+        StatementKind::Block { statements } => compile_block(ctx, statements),
+        StatementKind::FunctionCall { identifier, arguments } => compile_call(ctx, identifier, arguments),
+        StatementKind::Return { value } => {
+            let return_label = ctx.return_label.clone().expect(UNCHECKED);
+
+            if let Some(value) = value {
+                compile_expression(ctx, value);
+            }
+            ctx.write(&format!("jmp {}", return_label));
+        },
+        StatementKind::While { condition, statements } => {
             let loop_label = ctx.new_label();
             let end_label = ctx.new_label();
             ctx.write(&format!("{}:", loop_label));
 
-            compile_expression(ctx, condition);
-            ctx.write("cmp ax, 0");
-            ctx.write(&format!("je {}", end_label));
+            compile_branch(ctx, condition, &end_label, false);
+            compile_loop_body(ctx, statements, &end_label, &loop_label);
+            ctx.write(&format!("jmp {}", loop_label));
+            ctx.write(&format!("{}:", end_label));
+        },
+        StatementKind::For { initializer, condition, step, statements } => {
+            // The initializer's declarations are scoped to the loop
+            ctx.enter_scope();
+            if let Some(initializer) = initializer {
+                compile_statement(ctx, initializer);
+            }
+
+            let loop_label = ctx.new_label();
+            let step_label = ctx.new_label();
+            let end_label = ctx.new_label();
+            ctx.write(&format!("{}:", loop_label));
 
-            for s in statements {
-                compile_statement(ctx, s);
+            if let Some(condition) = condition {
+                compile_branch(ctx, condition, &end_label, false);
             }
+            compile_loop_body(ctx, statements, &end_label, &step_label);
 
+            ctx.write(&format!("{}:", step_label));
+            if let Some(step) = step {
+                compile_statement(ctx, step);
+            }
             ctx.write(&format!("jmp {}", loop_label));
             ctx.write(&format!("{}:", end_label));
+            ctx.exit_scope();
+        },
+        StatementKind::Break => ctx.write_loop_jump(|labels| &labels.break_label),
+        StatementKind::Continue => ctx.write_loop_jump(|labels| &labels.continue_label),
+        StatementKind::If { condition, statements, else_statements } => {
+            let else_label = ctx.new_label();
+            compile_branch(ctx, condition, &else_label, false);
+            compile_block(ctx, statements);
+
+            if else_statements.is_empty() {
+                ctx.write(&format!("{}:", else_label));
+            } else {
+                let end_label = ctx.new_label();
+                ctx.write(&format!("jmp {}", end_label));
+                ctx.write(&format!("{}:", else_label));
+                compile_block(ctx, else_statements);
+
+                ctx.write(&format!("{}:", end_label));
+            }
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ parser, tokenizer };
+    use super::emulator::Machine;
+
+    fn program(code: &str) -> Program {
+        let (program, errors) = parser::parse(tokenizer::tokenize(code.to_string()).unwrap());
+        assert_eq!(errors, vec![]);
+        program
+    }
+
+    /// The assembly generated for just the named function
+    fn function_body(assembly: &str, name: &str) -> Vec<String> {
+        assembly.lines()
+            .skip_while(|line| *line != format!("{}:", function_label(name)))
+            .skip(1)
+            .take_while(|line| *line != "ret")
+            .map(|line| line.to_string())
+            .collect()
+    }
+
+    struct FailingWriter;
+
+    impl Write for FailingWriter {
+        fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+            Err(io::Error::other("disk full"))
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn generates_into_memory() {
+        let mut buffer = vec![];
+        generate(&mut buffer, program("fn main() { print(33); }"), Layout::BootSector).unwrap();
+        let assembly = String::from_utf8(buffer).unwrap();
+
+        assert!(assembly.starts_with("bits 16\norg 0x7c00\n"));
+        assert!(assembly.ends_with("times 510 - ($-$$) db 0\ndw 0xaa55\n"));
+        assert_eq!(
+            function_body(&assembly, "main"),
+            vec!["push bp", "mov bp, sp", "mov ax, 33", "push ax", "call fn_print", "add sp, 2", ".return:", "mov sp, bp", "pop bp"]
+        );
+    }
+
+    #[test]
+    fn zeroes_the_segments_before_using_them() {
+        for layout in [Layout::BootSector, Layout::Floppy] {
+            let assembly = generate_string(program("let x = 1; fn main() { }"), layout).unwrap();
+            let prologue: Vec<&str> = assembly.lines()
+                .skip_while(|line| *line != "prologue:")
+                .skip(1)
+                .take(8)
+                .collect();
+
+            assert_eq!(
+                prologue,
+                vec!["cli", "xor ax, ax", "mov ds, ax", "mov es, ax", "mov ss, ax", "mov bp, $$", "mov sp, $$", "sti"],
+                "{:?}", layout
+            );
+        }
+    }
+
+    #[test]
+    fn generates_local_variables() {
+        let assembly = generate_string(program("fn main() { let a = 1; let b = a; a = b; }"), Layout::BootSector).unwrap();
+
+        assert_eq!(
+            function_body(&assembly, "main"),
+            vec![
+                "push bp", "mov bp, sp",
+                "mov ax, 1", "push ax",
+                "mov ax, [bp - 2]", "push ax",
+                "mov ax, [bp - 4]", "mov [bp - 2], ax",
+                ".return:", "mov sp, bp", "pop bp",
+            ]
+        );
+    }
+
+    #[test]
+    fn initializes_globals_before_main() {
+        let code = r#"
+            let greeting = "Hi";
+            let count = 1;
+            count = count + 1;
+
+            fn main() {
+                print(count);
+            }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+
+        let lines: Vec<&str> = assembly.lines().collect();
+        let store = lines.iter().position(|line| *line == "mov [global_greeting], ax").unwrap();
+        let call = lines.iter().position(|line| *line == "call fn_main").unwrap();
+        assert!(store < call);
+
+        assert!(assembly.contains("global_greeting: dw 0\nglobal_count: dw 0\n"));
+        assert_eq!(
+            function_body(&assembly, "main"),
+            vec!["push bp", "mov bp, sp", "mov ax, [global_count]", "push ax", "call fn_print", "add sp, 2", ".return:", "mov sp, bp", "pop bp"]
+        );
+    }
+
+    #[test]
+    fn functions_write_globals_and_locals_shadow_them() {
+        let code = r#"
+            let x = 0;
+
+            fn set() {
+                x = 5;
+            }
+
+            fn shadow(x) {
+                print(x);
+            }
+
+            fn main() {
+                set();
+                shadow(1);
+            }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+
+        assert_eq!(
+            function_body(&assembly, "set"),
+            vec!["push bp", "mov bp, sp", "mov ax, 5", "mov [global_x], ax", ".return:", "mov sp, bp", "pop bp"]
+        );
+        assert_eq!(
+            function_body(&assembly, "shadow"),
+            vec!["push bp", "mov bp, sp", "mov ax, [bp + 4]", "push ax", "call fn_print", "add sp, 2", ".return:", "mov sp, bp", "pop bp"]
+        );
+    }
+
+    #[test]
+    fn blocks_scope_and_shadow_variables() {
+        let code = r#"
+            let x = 1;
+
+            fn main() {
+                print(48 + x);
+                let x = 2;
+                {
+                    print(48 + x);
+                    let x = x + 1;
+                    x = x + 1;
+                    print(48 + x);
+                }
+                print(48 + x);
+                if (x == 2) {
+                    let x = 7;
+                    print(48 + x);
+                }
+                print(48 + x);
+            }
+        "#;
+        assert_eq!(run(code).output(), "124272");
+    }
+
+    #[test]
+    fn reclaims_stack_space_at_scope_exit() {
+        let code = r#"
+            let total = 0;
+
+            fn main() {
+                let i = 0;
+                while (i < 2000) {
+                    let doubled = i * 2;
+                    let tripled = i * 3;
+                    total = total + tripled - doubled - i;
+                    i = i + 1;
+                }
+            }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+        assert!(function_body(&assembly, "main").contains(&"add sp, 4".to_string()));
+
+        assert_eq!(Machine::run(&assembly).unwrap().global("total"), 0);
+    }
+
+    #[test]
+    fn top_level_blocks_use_the_stack() {
+        let code = r#"
+            let i = 0;
+            while (i < 3) {
+                let digit = 48 + i;
+                print(digit);
+                i = i + 1;
+            }
+
+            fn main() { }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+        let machine = Machine::run(&assembly).unwrap();
+
+        assert!(!assembly.contains("global_digit"));
+        assert_eq!(machine.output(), "012");
+        assert_eq!(machine.stack_pointer(), run("fn main() { }").stack_pointer());
+    }
+
+    #[test]
+    fn for_loops() {
+        let code = r#"
+            fn main() {
+                for (let i = 0; i < 5; i = i + 1) {
+                    print(48 + i);
+                }
+                let j = 3;
+                for (; j; ) {
+                    j = j - 1;
+                    print(97 + j);
+                }
+            }
+        "#;
+        assert_eq!(run(code).output(), "01234cba");
+    }
+
+    #[test]
+    fn break_and_continue() {
+        let code = r#"
+            let i = 0;
+            while (1) {
+                let a = i;
+                i = i + 1;
+                if (a == 1) {
+                    let b = 0;
+                    continue;
+                }
+                if (a == 3) {
+                    let b = 0;
+                    let c = 0;
+                    break;
+                }
+                print(48 + a);
+            }
+
+            for (let x = 0; x < 3; x = x + 1) {
+                for (let y = 0; ; y = y + 1) {
+                    if (y == x) {
+                        break;
+                    }
+                    if (y == 1) {
+                        continue;
+                    }
+                    print(97 + y);
+                }
+                print(124);
+            }
+
+            fn main() { }
+        "#;
+        let machine = run(code);
+
+        assert_eq!(machine.output(), "02|a|a|");
+        assert_eq!(machine.stack_pointer(), run("fn main() { }").stack_pointer());
+    }
+
+    #[test]
+    fn function_names_cant_clash_with_runtime_labels() {
+        let code = r#"
+            let x = 1;
+            fn epilogue() { print(49); }
+            fn prologue() { print(50); }
+            fn loader() { print(51); }
+            fn global_x() { print(52); }
+            fn string_0() { print(53); }
+            fn main() { epilogue(); prologue(); loader(); global_x(); string_0(); print("!"[0]); }
+        "#;
+
+        for layout in [Layout::BootSector, Layout::Floppy] {
+            let assembly = generate_string(program(code), layout).unwrap();
+            let mut labels: Vec<&str> = assembly.lines()
+                .filter_map(|line| line.split_once(':').map(|(label, _)| label))
+                .filter(|label| !label.starts_with('.') && !label.contains(' '))
+                .collect();
+            let count = labels.len();
+            labels.sort();
+            labels.dedup();
+            assert_eq!(labels.len(), count, "{}", assembly);
+        }
+
+        assert_eq!(run(code).output(), "12345!");
+    }
+
+    #[test]
+    fn emits_string_literals() {
+        let assembly = generate_string(program(r#"fn main() { print("Hi"); }"#), Layout::BootSector).unwrap();
+
+        assert!(assembly.contains("mov ax, string_0\n"));
+        assert!(assembly.contains("string_0: db \"Hi\", 0\n"));
+    }
+
+    #[test]
+    fn interns_strings_in_order_of_first_use() {
+        let code = r#"
+            let a = "one";
+            fn main() {
+                let b = "two";
+                let c = "one";
+                let d = "three";
+                let e = "two";
+            }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+        let strings: Vec<&str> = assembly.lines()
+            .filter(|line| line.starts_with("string_"))
+            .collect();
+
+        assert_eq!(strings, vec![
+            "string_0: db \"one\", 0",
+            "string_1: db \"two\", 0",
+            "string_2: db \"three\", 0",
+        ]);
+        assert_eq!(assembly, generate_string(program(code), Layout::BootSector).unwrap());
+    }
+
+    #[test]
+    fn emits_unprintable_string_bytes_as_numbers() {
+        assert_eq!(string_operands(b""), "0");
+        assert_eq!(string_operands(b"Hi"), "\"Hi\", 0");
+        assert_eq!(string_operands(b"say \"hi\"\n"), "\"say \", 34, \"hi\", 34, 10, 0");
+        assert_eq!(string_operands(&[0x82, b';', b'\'', b'`', 0xff]), "130, \";'`\", 255, 0");
+    }
+
+    #[test]
+    fn prints_escaped_strings() {
+        let code = r#"
+            fn print_string(string) {
+                for (let i = 0; string[i] != 0; i = i + 1) {
+                    print(string[i]);
+                }
+            }
+
+            fn main() {
+                print_string("\"caf\xe9\"; 'x'\r\n\t\\ é");
+            }
+        "#;
+
+        assert_eq!(run(code).output(), "\"caf\u{e9}\"; 'x'\r\n\t\\ \u{82}");
+    }
+
+    /// Compile `code` and run it in the emulator
+    fn run(code: &str) -> Machine {
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+        Machine::run(&assembly).unwrap_or_else(|e| panic!("{}\n{}", e, assembly))
+    }
+
+    /// The value `expression` evaluates to given the top level `setup`
+    fn evaluate(setup: &str, expression: &str) -> i16 {
+        run(&format!("{}\nlet result = {};\nfn main() {{ }}", setup, expression)).global("result")
+    }
+
+    #[test]
+    fn runs_the_hello_world_example() {
+        let code = r#"
+            fn print_string(string) {
+                let i = 0;
+                while (string[i] != 0) {
+                    print(string[i]);
+                    i = i + 1;
+                }
+            }
+
+            fn main() {
+                print_string("Hello, World!");
+            }
+        "#;
+
+        assert_eq!(run(code).output(), "Hello, World!");
+    }
+
+    #[test]
+    fn chained_additions() {
+        let setup = "let a = 1; let b = 2; let c = 3;";
+
+        assert_eq!(evaluate(setup, "a + b"), 3);
+        assert_eq!(evaluate(setup, "a + b + c"), 6);
+        assert_eq!(evaluate(setup, "c + b + a + 10"), 16);
+        assert_eq!(evaluate(setup, "1 + 2 + 3 + 4 + 5 + 6 + 7 + 8 + 9 + 10"), 55);
+    }
+
+    #[test]
+    fn nested_lookups() {
+        let setup = r#"let s = "abcabc"; let i = 0; let j = 3;"#;
+
+        assert_eq!(evaluate(setup, "s[i]"), 'a' as i16);
+        assert_eq!(evaluate(setup, "s[i] != s[j]"), 0);
+        assert_eq!(evaluate(setup, "s[i + 1] != s[j]"), 1);
+        assert_eq!(evaluate(setup, "s[i + 1] + s[j + 2]"), ('b' as i16) + ('c' as i16));
+        assert_eq!(evaluate(setup, "s[s[j] != s[i] + j]"), 'b' as i16);
+    }
+
+    #[test]
+    fn comparisons_of_sums() {
+        let setup = "let a = 1; let b = 2; let c = 3;";
+
+        assert_eq!(evaluate(setup, "c != a + b"), 0);
+        assert_eq!(evaluate(setup, "a != b + c"), 1);
+        assert_eq!(evaluate(setup, "c != a + b + c + a + b"), 1);
+        assert_eq!(evaluate(setup, "a + b != c"), 0);
+        assert_eq!(evaluate(setup, "(a + b != c) + (a + b + c != c + b + a + 1)"), 1);
+    }
+
+    #[test]
+    fn signed_arithmetic() {
+        let setup = "let a = 7; let b = 2; let c = 0 - 3;";
+
+        assert_eq!(evaluate(setup, "a - b - 1"), 4);
+        assert_eq!(evaluate(setup, "a * b + c"), 11);
+        assert_eq!(evaluate(setup, "a * c"), -21);
+        assert_eq!(evaluate(setup, "a / b"), 3);
+        assert_eq!(evaluate(setup, "-a / b"), -3);
+        assert_eq!(evaluate(setup, "a / -b"), -3);
+        assert_eq!(evaluate(setup, "a % b"), 1);
+        assert_eq!(evaluate(setup, "-a % b"), -1);
+        assert_eq!(evaluate(setup, "a % c"), 1);
+        assert_eq!(evaluate(setup, "-c"), 3);
+        assert_eq!(evaluate(setup, "--c"), -3);
+        assert_eq!(evaluate(setup, "(a - b) * (a + c) / (b - a)"), -4);
+    }
+
+    #[test]
+    fn arithmetic_wraps_at_16_bits() {
+        assert_eq!(evaluate("let max = 32767;", "max + 1"), -32768);
+        assert_eq!(evaluate("let max = 32767;", "max * 2"), -2);
+        assert_eq!(evaluate("let max = 32767;", "-max - 2"), 32767);
+    }
+
+    #[test]
+    fn signed_comparisons() {
+        let setup = "let a = 0 - 2; let b = 3;";
+        let cases = [
+            ("a == b", 0), ("a == a", 1), ("a != b", 1), ("a != a", 0),
+            ("a < b", 1), ("b < a", 0), ("a < a", 0),
+            ("a <= b", 1), ("b <= a", 0), ("a <= a", 1),
+            ("a > b", 0), ("b > a", 1), ("a > a", 0),
+            ("a >= b", 0), ("b >= a", 1), ("a >= a", 1),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(evaluate(setup, expression), expected, "{}", expression);
+        }
+
+        assert_eq!(evaluate("", "-32768 < 0"), 1);
+        assert_eq!(evaluate("let m = -32768;", "m < 0"), 1);
+    }
+
+    #[test]
+    fn unsigned_literals_share_bits_with_negative_numbers() {
+        assert_eq!(evaluate("", "0xFFFF"), -1);
+        assert_eq!(evaluate("", "0xFFFF == -1"), 1);
+        assert_eq!(evaluate("", "-32768"), i16::MIN);
+    }
+
+    #[test]
+    fn unsigned_operations() {
+        assert_eq!(evaluate("", "0x7c00 < 0xB800"), 1);
+        assert_eq!(evaluate("", "0xB800 <= 0x7c00"), 0);
+        assert_eq!(evaluate("", "40000 < 0"), 0);
+        assert_eq!(evaluate("", "40000 > 1"), 1);
+        assert_eq!(evaluate("", "-1 < 0"), 1);
+        assert_eq!(evaluate("", "0xB800 >> 8"), 0xb8);
+        assert_eq!(evaluate("", "-0x4800 >> 8"), -72);
+        assert_eq!(evaluate("", "60000 / 2"), 30000);
+        assert_eq!(evaluate("", "60000 % 7") as u16, 60000 % 7);
+        assert_eq!(evaluate("", "-7 / 2"), -3);
+        assert_eq!(evaluate("", "-32768 / 2"), -16384);
+
+        let setup = "let segment = 0xB800; let offset = 0x7c00; let low = 0;";
+        assert_eq!(evaluate(setup, "offset < segment"), 1);
+        assert_eq!(evaluate(setup, "segment > offset && segment > low"), 1);
+        assert_eq!(evaluate(setup, "segment + 1 > offset"), 1);
+        assert_eq!(evaluate(setup, "segment >> 12"), 0xb);
+
+        let code = r#"
+            fn main() {
+                let segment = 0xA000;
+                while (segment < 0xC000) {
+                    print(48 + segment / 0x1000);
+                    segment = segment + 0x800;
+                }
+            }
+        "#;
+        assert_eq!(run(code).output(), "::;;");
+    }
+
+    #[test]
+    fn bitwise_operators() {
+        let setup = "let a = 12; let b = 10;";
+
+        assert_eq!(evaluate(setup, "a & b"), 8);
+        assert_eq!(evaluate(setup, "a | b"), 14);
+        assert_eq!(evaluate(setup, "a ^ b"), 6);
+        assert_eq!(evaluate(setup, "~a"), -13);
+        assert_eq!(evaluate(setup, "a << 2"), 48);
+        assert_eq!(evaluate(setup, "a >> 2"), 3);
+        assert_eq!(evaluate(setup, "-a >> 2"), -3);
+        assert_eq!(evaluate(setup, "1 << 15 >> 15"), -1);
+        assert_eq!(evaluate(setup, "a & 3 == 0"), 0);
+        assert_eq!(evaluate(setup, "(a & 3) == 0"), 1);
+    }
+
+    #[test]
+    fn logical_operators() {
+        let setup = "let t = 5; let f = 0;";
+        let cases = [
+            ("t && t", 1), ("t && f", 0), ("f && t", 0), ("f && f", 0),
+            ("t || t", 1), ("t || f", 1), ("f || t", 1), ("f || f", 0),
+            ("!t", 0), ("!f", 1), ("!!t", 1),
+            ("t > 1 && !(f < 0)", 1), ("f || t < 0 || !f && t", 1),
+        ];
+
+        for (expression, expected) in cases {
+            assert_eq!(evaluate(setup, expression), expected, "{}", expression);
         }
     }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        // Dividing by zero faults, so the right hand sides must never run
+        let setup = "let t = 5; let f = 0;";
+
+        assert_eq!(evaluate(setup, "f && 1 / f"), 0);
+        assert_eq!(evaluate(setup, "t || 1 / f"), 1);
+        assert_eq!(evaluate(setup, "!(t || 1 / f) && 1 / f"), 0);
+
+        let code = r#"
+            fn main() {
+                let zero = 0;
+                let i = 0;
+                while (i < 1 && (i == 0 || 1 / zero)) {
+                    print(65 + i);
+                    i = i + 1;
+                }
+                while (!(i != 0 || 1 / zero)) {
+                    print(90);
+                }
+            }
+        "#;
+        assert_eq!(run(code).output(), "A");
+    }
+
+    #[test]
+    fn if_else_chains() {
+        let code = r#"
+            fn classify(n) {
+                if (n < 0) {
+                    print(45);
+                } else if (n == 0) {
+                    print(48);
+                } else if (n < 10) {
+                    print(115);
+                } else {
+                    print(98);
+                }
+                if (n == 5) {
+                    print(33);
+                }
+            }
+
+            fn main() {
+                classify(0 - 3);
+                classify(0);
+                classify(5);
+                classify(50);
+            }
+        "#;
+        assert_eq!(run(code).output(), "-0s!b");
+    }
+
+    #[test]
+    fn functions_return_values() {
+        let code = r#"
+            let greeting = "Hello";
+
+            fn strlen(s) {
+                let n = 0;
+                while (s[n]) {
+                    n = n + 1;
+                }
+                return n;
+            }
+
+            fn factorial(n) {
+                if (n < 2) {
+                    return 1;
+                }
+                return n * factorial(n - 1);
+            }
+
+            let length = strlen(greeting) + 1;
+            let product = factorial(5) - factorial(3);
+
+            fn main() { }
+        "#;
+        let machine = run(code);
+
+        assert_eq!(machine.global("length"), 6);
+        assert_eq!(machine.global("product"), 114);
+    }
+
+    #[test]
+    fn early_returns_skip_the_rest_of_the_function() {
+        let code = r#"
+            fn first_even(s) {
+                let i = 0;
+                while (s[i]) {
+                    if (s[i] % 2 == 0) {
+                        print(s[i]);
+                        return i;
+                    }
+                    i = i + 1;
+                }
+                print(63);
+                return 0 - 1;
+            }
+
+            fn main() {
+                print(48 + first_even("ab"));
+                print(48 + first_even("ace") + first_even("on"));
+            }
+        "#;
+        assert_eq!(run(code).output(), "b1?n0");
+    }
+
+    #[test]
+    #[should_panic(expected = "the program should have passed semantic analysis")]
+    fn expects_returns_to_be_checked_by_sema() {
+        let _ = generate_string(program("return 1; fn main() { }"), Layout::BootSector);
+    }
+
+    #[test]
+    fn passes_arguments_on_the_stack() {
+        let code = r#"
+            fn sub3(a, b, c) {
+                let d = a - b;
+                return d - c;
+            }
+
+            fn call_order(a, b) {
+                print(a);
+                print(b);
+                return b;
+            }
+
+            let result = sub3(10, 3, 2);
+            let nested = sub3(sub3(20, 5, 1), call_order(65, 66) - 60, 0);
+
+            fn main() { }
+        "#;
+        let machine = run(code);
+
+        assert_eq!(machine.global("result"), 5);
+        assert_eq!(machine.global("nested"), 8);
+        assert_eq!(machine.output(), "AB");
+    }
+
+    #[test]
+    fn callers_clean_up_arguments() {
+        let assembly = generate_string(program("fn f(a, b) { } fn main() { f(1, 2); }"), Layout::BootSector).unwrap();
+
+        assert_eq!(
+            function_body(&assembly, "main"),
+            vec![
+                "push bp", "mov bp, sp",
+                "mov ax, 2", "push ax", "mov ax, 1", "push ax", "call fn_f", "add sp, 4",
+                ".return:", "mov sp, bp", "pop bp",
+            ]
+        );
+        // Loops that call functions must not leak stack space
+        let machine = run("let i = 0; while (i < 1000) { i = i + 1; print(0); } fn main() { }");
+        assert_eq!(machine.output().len(), 1000);
+    }
+
+    #[test]
+    fn while_conditions_jump_on_comparisons() {
+        let code = r#"
+            fn main() {
+                let i = 0;
+                while (i < 3) {
+                    print(48 + i);
+                    i = i + 1;
+                }
+            }
+        "#;
+        let assembly = generate_string(program(code), Layout::BootSector).unwrap();
+        let main = function_body(&assembly, "main");
+
+        assert!(main.iter().any(|line| line.starts_with("jge ")), "{:?}", main);
+        assert!(!main.iter().any(|line| line.starts_with("setl")), "{:?}", main);
+        assert_eq!(Machine::run(&assembly).unwrap().output(), "012");
+    }
+
+    #[test]
+    fn while_conditions_can_be_plain_values() {
+        let code = r#"
+            fn main() {
+                let i = 3;
+                while (i) {
+                    print(48 + i);
+                    i = i - 1;
+                }
+            }
+        "#;
+        assert_eq!(run(code).output(), "321");
+    }
+
+    #[test]
+    #[should_panic(expected = "the program should have passed semantic analysis")]
+    fn expects_variables_to_be_checked_by_sema() {
+        let _ = generate_string(program("fn main() { print(nope); }"), Layout::BootSector);
+    }
+
+    #[test]
+    fn reports_write_errors() {
+        let result = generate(&mut FailingWriter, program("fn main() { }"), Layout::BootSector);
+        assert!(matches!(result, Err(CodegenError::Io(_))));
+    }
+
+    #[test]
+    fn reports_programs_too_large_for_the_boot_sector() {
+        let prints = "print(33);".repeat(50);
+        let code = format!("fn greet() {{ {} }}\nfn main() {{ greet(); }}", prints);
+
+        let (size, sections) = match generate_string(program(&code), Layout::BootSector) {
+            Err(CodegenError::ProgramTooLarge { size, sections, .. }) => (size, sections),
+            result => panic!("expected the program to be too large, got {:?}", result),
+        };
+
+        assert!(size > BOOT_SECTOR_BUDGET);
+        assert_eq!(size, sections.iter().map(|(_, size)| size).sum());
+        let names: Vec<&str> = sections.iter().map(|(name, _)| name.as_str()).collect();
+        assert_eq!(names, vec!["prologue", "print", "greet", "main", "epilogue", "globals", "strings"]);
+        // Each call is `mov ax, 33`, `push ax`, `call fn_print` and `add sp, 2`, around
+        // 7 bytes of saving and restoring bp
+        assert!(sections.contains(&("greet".to_string(), 50 * 10 + 7)));
+
+        let diagnostic = CodegenError::ProgramTooLarge { layout: Layout::BootSector, size, sections }.to_diagnostic();
+        assert_eq!(diagnostic.message, format!("program is {} bytes, boot sector budget is 510", size));
+        assert!(diagnostic.notes.contains(&"`greet` takes 507 bytes".to_string()));
+    }
+
+    #[test]
+    fn writes_nothing_for_programs_too_large() {
+        let prints = "print(33);".repeat(60);
+        let mut buffer = vec![];
+        let result = generate(&mut buffer, program(&format!("fn main() {{ {} }}", prints)), Layout::BootSector);
+
+        assert!(matches!(result, Err(CodegenError::ProgramTooLarge { .. })));
+        assert!(buffer.is_empty());
+    }
+
+    #[test]
+    fn accepts_programs_that_fit() {
+        let prints = "print(33);".repeat(40);
+        let result = generate_string(program(&format!("fn main() {{ {} }}", prints)), Layout::BootSector);
+
+        assert!(result.is_ok());
+    }
+
+    #[test]
+    fn loads_programs_too_large_for_the_boot_sector_from_a_floppy() {
+        let prints = "print(33);".repeat(100);
+        let code = format!("fn main() {{ {} }}", prints);
+        assert!(generate_string(program(&code), Layout::BootSector).is_err());
+
+        let assembly = generate_string(program(&code), Layout::Floppy).unwrap();
+        assert!(assembly.starts_with("bits 16\norg 0x7c00\n\nloader:\n"));
+        assert!(assembly.contains("times 510 - ($-$$) db 0\ndw 0xaa55\n"));
+        assert!(assembly.ends_with("times 1474560 - ($-$$) db 0\n"));
+
+        let machine = Machine::run(&assembly).unwrap_or_else(|e| panic!("{}\n{}", e, assembly));
+        assert_eq!(machine.output(), "!".repeat(100));
+    }
+
+    #[test]
+    fn reports_programs_too_large_for_the_floppy_loader() {
+        let prints = "print(33);".repeat(900);
+        let result = generate_string(program(&format!("fn main() {{ {} }}", prints)), Layout::Floppy);
+
+        let error = result.unwrap_err();
+        assert!(matches!(error, CodegenError::ProgramTooLarge { layout: Layout::Floppy, .. }));
+        assert!(error.to_diagnostic().message.ends_with("floppy loader budget is 8704"));
+    }
 }
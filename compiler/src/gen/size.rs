@@ -0,0 +1,216 @@
+//! Upper bounds on how many bytes NASM assembles the generated code into,
+//! so programs that can't fit the boot sector are caught by the compiler.
+
+/// Longest any x86 instruction can be, used for anything not recognised
+const LONGEST_INSTRUCTION: usize = 15;
+
+const REGISTERS_16: &[&str] = &["ax", "bx", "cx", "dx", "si", "di", "bp", "sp"];
+const REGISTERS_8: &[&str] = &["al", "ah", "bl", "bh", "cl", "ch", "dl", "dh"];
+const SEGMENT_REGISTERS: &[&str] = &["cs", "ds", "es", "ss"];
+
+/// The most bytes one line of generated assembly can take. Jumps are counted
+/// at their near size even though NASM may shorten them, and directives that
+/// only pad, such as `times`, count as nothing.
+pub fn upper_bound(line: &str) -> usize {
+    let mut line = strip_comment(line).trim();
+    if let Some((label, rest)) = line.split_once(':') {
+        if !label.contains(char::is_whitespace) {
+            line = rest.trim();
+        }
+    }
+    if line.is_empty() {
+        return 0;
+    }
+
+    let (mnemonic, operands) = line.split_once(char::is_whitespace).unwrap_or((line, ""));
+    let operands = split_operands(operands);
+
+    match (mnemonic, operands.as_slice()) {
+        ("bits" | "org" | "times", _) => 0,
+        ("db", _) => operands.iter()
+            .map(|operand| if operand.starts_with(QUOTES) { operand.len() - 2 } else { 1 })
+            .sum(),
+        ("dw", _) => 2 * operands.len(),
+        ("push" | "pop", [register]) if is_register(register) => 1,
+        ("ret" | "cli" | "sti" | "hlt" | "cwd", []) => 1,
+        ("int", [_]) => 2,
+        // Near forms: an opcode and a 16 bit displacement
+        ("call" | "jmp", [_]) => 3,
+        (jump, [_]) if jump.starts_with('j') => 4,
+        (set, [register]) if set.starts_with("set") && is_register(register) => 3,
+        ("neg" | "not" | "imul" | "idiv" | "div", [register]) if is_register(register) => 2,
+        ("shl" | "shr" | "sar", [register, count]) if is_register(register) && count == "cl" => 2,
+        ("mov", [segment, register]) if SEGMENT_REGISTERS.contains(&segment.as_str()) && is_register(register) => 2,
+        ("mov", [register, immediate]) if is_immediate(immediate) => {
+            if REGISTERS_8.contains(&register.as_str()) { 2 } else { 3 }
+        },
+        ("mov" | "add" | "sub" | "and" | "or" | "xor" | "cmp", [destination, source]) => {
+            two_operands(mnemonic, destination, source)
+        },
+        _ => LONGEST_INSTRUCTION,
+    }
+}
+
+/// Arithmetic and moves between registers, memory and immediates
+fn two_operands(mnemonic: &str, destination: &str, source: &str) -> usize {
+    match (is_register(destination), is_register(source)) {
+        (true, true) => 2,
+        (true, false) | (false, true) if is_memory(destination) || is_memory(source) => {
+            let (register, memory) = if is_memory(source) { (destination, source) } else { (source, destination) };
+            let address = &memory[1..memory.len() - 1];
+
+            // `mov ax, [label]` and back have a form without a ModRM byte
+            if mnemonic == "mov" && (register == "ax" || register == "al") && !mentions_register(address) {
+                3
+            } else {
+                2 + displacement(address)
+            }
+        },
+        (true, false) => match immediate_value(source) {
+            Some(value) if (-128..=127).contains(&value) => 3,
+            _ => if REGISTERS_8.contains(&destination) { 3 } else { 4 },
+        },
+        _ => LONGEST_INSTRUCTION,
+    }
+}
+
+/// Bytes of displacement needed to encode the address inside `[]`
+fn displacement(address: &str) -> usize {
+    if !mentions_register(address) {
+        return 2;
+    }
+
+    let offset = address.split(['+', '-'])
+        .map(str::trim)
+        .find(|part| !is_register(part));
+    match offset.and_then(immediate_value) {
+        Some(value) if (-128..=127).contains(&value) => 1,
+        Some(_) => 2,
+        // Labels can be anywhere, and bp on its own still needs a zero
+        None if offset.is_some() => 2,
+        None if address.trim() == "bp" => 1,
+        None => 0,
+    }
+}
+
+fn is_register(operand: &str) -> bool {
+    REGISTERS_16.contains(&operand) || REGISTERS_8.contains(&operand)
+}
+
+fn is_memory(operand: &str) -> bool {
+    operand.starts_with('[') && operand.ends_with(']')
+}
+
+fn is_immediate(operand: &str) -> bool {
+    !is_register(operand) && !is_memory(operand)
+}
+
+fn mentions_register(address: &str) -> bool {
+    address.split(['+', '-']).any(|part| is_register(part.trim()))
+}
+
+/// The value of a plain number, if `operand` is one
+fn immediate_value(operand: &str) -> Option<i64> {
+    match operand.strip_prefix("0x") {
+        Some(hex) => i64::from_str_radix(hex, 16).ok(),
+        None => operand.parse().ok(),
+    }
+}
+
+/// NASM quotes strings with any of these, and `;` or `,` inside one is text
+const QUOTES: [char; 3] = ['"', '\'', '`'];
+
+/// `line` without its comment, if it has one. Shared with the emulator so
+/// both read every line the same way.
+pub(super) fn strip_comment(line: &str) -> &str {
+    let mut quote = None;
+    for (i, c) in line.char_indices() {
+        match (quote, c) {
+            (None, ';') => return &line[..i],
+            (None, c) if QUOTES.contains(&c) => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => (),
+        }
+    }
+    line
+}
+
+/// Split on commas that aren't inside a quoted string
+pub(super) fn split_operands(text: &str) -> Vec<String> {
+    let mut operands = vec![];
+    let mut current = String::new();
+    let mut quote = None;
+
+    for c in text.chars() {
+        match (quote, c) {
+            (None, ',') => {
+                operands.push(current.trim().to_string());
+                current.clear();
+                continue;
+            },
+            (None, c) if QUOTES.contains(&c) => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            _ => (),
+        }
+        current.push(c);
+    }
+
+    if !current.trim().is_empty() {
+        operands.push(current.trim().to_string());
+    }
+    operands
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sizes_instructions_by_their_encoding() {
+        assert_eq!(upper_bound("push ax"), 1);
+        assert_eq!(upper_bound("ret"), 1);
+        assert_eq!(upper_bound("mov ax, 1234"), 3);
+        assert_eq!(upper_bound("mov ah, 0"), 2);
+        assert_eq!(upper_bound("mov ax, string_0"), 3);
+        assert_eq!(upper_bound("mov bp, ($$ + 510)"), 3);
+        assert_eq!(upper_bound("mov bx, ax"), 2);
+        assert_eq!(upper_bound("mov ss, ax"), 2);
+        assert_eq!(upper_bound("sti"), 1);
+        assert_eq!(upper_bound("mov ax, [bp - 2]"), 3);
+        assert_eq!(upper_bound("mov [bp + 300], ax"), 4);
+        assert_eq!(upper_bound("mov al, [bx + si]"), 2);
+        assert_eq!(upper_bound("mov ax, [global_x]"), 3);
+        assert_eq!(upper_bound("mov [global_x], ax"), 3);
+        assert_eq!(upper_bound("add sp, 4"), 3);
+        assert_eq!(upper_bound("add sp, 200"), 4);
+        assert_eq!(upper_bound("cmp ax, 0"), 3);
+        assert_eq!(upper_bound("setle al"), 3);
+        assert_eq!(upper_bound("shl ax, cl"), 2);
+        assert_eq!(upper_bound("idiv bx"), 2);
+        assert_eq!(upper_bound("int 0x10"), 2);
+    }
+
+    #[test]
+    fn counts_jumps_at_their_near_size() {
+        assert_eq!(upper_bound("jmp .label_0"), 3);
+        assert_eq!(upper_bound("call print"), 3);
+        assert_eq!(upper_bound("jge .label_1"), 4);
+    }
+
+    #[test]
+    fn sizes_data_and_directives() {
+        assert_eq!(upper_bound("string_0: db \"Hi, \", 34, \"you\", 34, 0"), 10);
+        assert_eq!(upper_bound("global_x: dw 0"), 2);
+        assert_eq!(upper_bound("dw 0, 1"), 4);
+        assert_eq!(upper_bound("main:"), 0);
+        assert_eq!(upper_bound("    mov ah,0x0e ; teletype"), 2);
+        assert_eq!(upper_bound("db ';', 'a,b', 0 ; both quotes"), 5);
+        assert_eq!(upper_bound("times 510 - ($-$$) db 0"), 0);
+        assert_eq!(upper_bound("org 0x7c00"), 0);
+    }
+
+    #[test]
+    fn assumes_the_worst_for_anything_else() {
+        assert_eq!(upper_bound("lgdt [gdt]"), LONGEST_INSTRUCTION);
+    }
+}
@@ -1,26 +1,145 @@
-use std::fs::File;
-use std::io::BufWriter;
-use std::io::Read;
+use std::fmt;
+use std::io;
+use std::io::{ Read, Write };
 
+pub mod span;
+pub mod diagnostics;
 mod tokenizer;
 mod parser;
+mod sema;
 mod gen;
 
-pub fn compile(mut input_file: File, output_file: File) {
+use diagnostics::Diagnostic;
+pub use gen::Layout;
+
+#[derive(Debug, Clone, Default)]
+pub struct Options {
+    pub layout: Layout,
+}
+
+/// The result of a successful compilation
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Artifact {
+    /// NASM source for the disk image
+    pub assembly: String,
+}
+
+#[derive(Debug)]
+pub enum CompileError {
+    Io(io::Error),
+    Tokenization(Vec<Diagnostic>),
+    Parse(Vec<Diagnostic>),
+    Semantic(Vec<Diagnostic>),
+    Codegen(Vec<Diagnostic>),
+}
+
+impl CompileError {
+    /// Everything that went wrong, ready to be rendered against the source
+    pub fn diagnostics(&self) -> Vec<Diagnostic> {
+        match self {
+            CompileError::Io(e) => vec![Diagnostic::error(e.to_string())],
+            CompileError::Tokenization(diagnostics) => diagnostics.clone(),
+            CompileError::Parse(diagnostics) => diagnostics.clone(),
+            CompileError::Semantic(diagnostics) => diagnostics.clone(),
+            CompileError::Codegen(diagnostics) => diagnostics.clone(),
+        }
+    }
+}
+
+impl fmt::Display for CompileError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CompileError::Io(e) => write!(f, "I/O error: {}", e),
+            CompileError::Tokenization(diagnostics) => write!(f, "tokenizing failed with {} error(s)", diagnostics.len()),
+            CompileError::Parse(diagnostics) => write!(f, "parsing failed with {} error(s)", diagnostics.len()),
+            CompileError::Semantic(diagnostics) => write!(f, "semantic analysis failed with {} error(s)", diagnostics.len()),
+            CompileError::Codegen(diagnostics) => write!(f, "code generation failed with {} error(s)", diagnostics.len()),
+        }
+    }
+}
+
+impl std::error::Error for CompileError {}
+
+impl From<io::Error> for CompileError {
+    fn from(e: io::Error) -> CompileError {
+        CompileError::Io(e)
+    }
+}
+
+fn run(code: &str, options: &Options) -> Result<Artifact, CompileError> {
+    let tokens = tokenizer::tokenize(code.to_string())
+        .map_err(|e| CompileError::Tokenization(vec![e.to_diagnostic()]))?;
+
+    let program = match parser::parse(tokens) {
+        (program, errors) if errors.is_empty() => program,
+        (_, errors) => return Err(CompileError::Parse(errors.iter().map(|e| e.to_diagnostic()).collect())),
+    };
+
+    let errors = sema::analyze(&program);
+    if !errors.is_empty() {
+        return Err(CompileError::Semantic(errors.iter().map(|e| e.to_diagnostic()).collect()));
+    }
+
+    let assembly = gen::generate_string(program, options.layout).map_err(|e| match e {
+        gen::CodegenError::Io(e) => CompileError::Io(e),
+        e => CompileError::Codegen(vec![e.to_diagnostic()]),
+    })?;
+
+    Ok(Artifact {
+        assembly,
+    })
+}
+
+/// Compile the source in `code`, returning every diagnostic on failure
+pub fn compile_str(code: &str, options: &Options) -> Result<Artifact, Vec<Diagnostic>> {
+    run(code, options).map_err(|e| e.diagnostics())
+}
+
+/// Compile the source read from `input`, writing the generated assembly to
+/// `output`. Nothing is written if compilation fails.
+pub fn compile<R: Read, W: Write>(mut input: R, mut output: W, options: &Options) -> Result<(), CompileError> {
     let mut code = String::new();
-    if let Err(e) = input_file.read_to_string(&mut code) {
-        println!("Unable to read file: {}", e);
+    input.read_to_string(&mut code)?;
+
+    let artifact = run(&code, options)?;
+    output.write_all(artifact.assembly.as_bytes())?;
+    output.flush()?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn compiles_from_a_string() {
+        let artifact = compile_str("fn main() { print(33); }", &Options::default()).unwrap();
+
+        assert!(artifact.assembly.starts_with("bits 16\n"));
+        assert!(artifact.assembly.contains("call fn_print"));
+    }
+
+    #[test]
+    fn compiles_floppy_images() {
+        let options = Options { layout: Layout::Floppy };
+        let artifact = compile_str("fn main() { print(33); }", &options).unwrap();
+
+        assert!(artifact.assembly.contains("int 0x13"));
+        assert!(artifact.assembly.ends_with("times 1474560 - ($-$$) db 0\n"));
+    }
+
+    #[test]
+    fn returns_diagnostics_for_every_error() {
+        let diagnostics = compile_str("fn main() { let = 1; print(; }", &Options::default()).unwrap_err();
+        assert_eq!(diagnostics.len(), 2);
     }
 
-    let mut writer = BufWriter::new(output_file);
+    #[test]
+    fn reports_failing_stage() {
+        let mut output = vec![];
+        let result = compile("fn main() { print(x); }".as_bytes(), &mut output, &Options::default());
 
-    match tokenizer::tokenize(code) {
-        Ok(tokens) => {
-            match parser::parse(tokens) {
-                Ok(program) => gen::generate(&mut writer, program),
-                Err(e) => println!("Error tokenizing: {:?}", e)
-            }
-        },
-        Err(e) => println!("Error tokenizing: {:?}", e)
+        assert!(matches!(result, Err(CompileError::Semantic(_))));
+        assert!(output.is_empty());
     }
 }
@@ -1,12 +1,24 @@
 use std::env;
-use std::fs::File;
+use std::fs;
 use std::path::Path;
 use std::ffi::OsStr;
+use std::process;
+use compiler::{ Layout, Options };
+use compiler::diagnostics::Diagnostic;
 
 fn main() {
     let args: Vec<String> = env::args().collect();
+    let mut exit_code = 0;
 
-    for filename in args[1..].iter() {
+    // `--floppy` builds a floppy image with a loader instead of a lone boot
+    // sector, for programs too big to fit one
+    let layout = if args[1..].iter().any(|arg| arg == "--floppy") {
+        Layout::Floppy
+    } else {
+        Layout::BootSector
+    };
+
+    for filename in args[1..].iter().filter(|arg| *arg != "--floppy") {
         let file_path = Path::new(filename);
         match file_path.extension().and_then(OsStr::to_str) {
             Some("bit") => (),
@@ -17,16 +29,42 @@ fn main() {
         }
 
         eprintln!("Opening input: {}", filename);
-        match File::open(filename) {
-            Ok(input_file) => {
-                let output_path = file_path.with_extension("asm");
-                eprintln!("Opening output: {}", output_path.display());
-                match File::create(&output_path) {
-                    Ok(output_file) => compiler::compile(input_file, output_file),
-                    Err(e) => println!("Could not open output file {}: {}", output_path.display(), e)
-                }
-            },
-            Err(e) => println!("Could not open input file {}: {}", filename, e)
+        // Read once, so diagnostics are rendered against the same source that
+        // was compiled
+        let code = match fs::read_to_string(filename) {
+            Ok(code) => code,
+            Err(e) => {
+                eprintln!("Could not read input file {}: {}", filename, e);
+                exit_code = exit_code.max(2);
+                continue;
+            }
+        };
+
+        let artifact = match compiler::compile_str(&code, &Options { layout }) {
+            Ok(artifact) => artifact,
+            Err(diagnostics) => {
+                report(filename, &code, &diagnostics);
+                exit_code = exit_code.max(1);
+                continue;
+            }
+        };
+
+        let output_path = file_path.with_extension("asm");
+        eprintln!("Writing output: {}", output_path.display());
+        if let Err(e) = fs::write(&output_path, artifact.assembly) {
+            eprintln!("Could not write output file {}: {}", output_path.display(), e);
+            exit_code = exit_code.max(2);
         }
     }
+
+    process::exit(exit_code);
+}
+
+/// Show every diagnostic with snippets of `code`. Compilation errors exit
+/// with 1, while problems reading or writing files exit with 2.
+fn report(filename: &str, code: &str, diagnostics: &[Diagnostic]) {
+    for diagnostic in diagnostics {
+        eprintln!("{}", diagnostic.render(filename, code));
+    }
+    eprintln!("Could not compile {}: {} error(s)", filename, diagnostics.len());
 }
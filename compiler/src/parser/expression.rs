@@ -1,10 +1,23 @@
 use super::*;
 
 #[derive(Debug, PartialEq, Eq)]
-pub enum Expression {
-    NumberLiteral(i16),
-    StringLiteral(String),
+pub struct Expression {
+    pub kind: ExpressionKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExpressionKind {
+    /// The literal's 16 bits. Ones too big for a signed value are unsigned,
+    /// which `sema::types` takes into account when typing expressions.
+    NumberLiteral(u16),
+    /// The bytes of the string, without the terminating zero
+    StringLiteral(Vec<u8>),
     Variable(String),
+    FunctionCall {
+        identifier: String,
+        arguments: Vec::<Expression>,
+    },
     Lookup {
         base: Box::<Expression>,
         index: Box::<Expression>,
@@ -13,127 +26,354 @@ pub enum Expression {
         left: Box::<Expression>,
         right: Box::<Expression>,
     },
+    EqualComparison {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    LessThanComparison {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    LessThanOrEqualComparison {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    GreaterThanComparison {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    GreaterThanOrEqualComparison {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
     Addition {
         left: Box::<Expression>,
         right: Box::<Expression>,
+    },
+    Subtraction {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    Multiplication {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    Division {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    Remainder {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    Negation {
+        operand: Box::<Expression>,
+    },
+    LogicalAnd {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    LogicalOr {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    BitwiseAnd {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    BitwiseOr {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    BitwiseXor {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    ShiftLeft {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    ShiftRight {
+        left: Box::<Expression>,
+        right: Box::<Expression>,
+    },
+    LogicalNot {
+        operand: Box::<Expression>,
+    },
+    BitwiseNot {
+        operand: Box::<Expression>,
+    },
+}
+
+#[cfg(test)]
+impl Expression {
+    pub fn unspanned(kind: ExpressionKind) -> Expression {
+        Expression { kind, span: Span::default() }
     }
 }
 
-fn get_value(token: Option<&Token>) -> Result<Expression, SyntaxError> {
-    Ok(match token {
-        Some(Token::Number(num)) => Expression::NumberLiteral(*num),
-        Some(Token::QuotedString(value)) => Expression::StringLiteral(value.clone()),
-        Some(Token::Identifier(value)) => Expression::Variable(value.clone()),
-        _ => return Err(SyntaxError::UnexpectedToken),
-    })
+/// How tightly each binary operator binds; higher binds tighter. Every
+/// binary operator is left associative, so `a + b + c` is `(a + b) + c`.
+///
+/// | Precedence | Operators           |
+/// |------------|---------------------|
+/// | 1          | `\|\|`              |
+/// | 2          | `&&`                |
+/// | 3          | `\|`                |
+/// | 4          | `^`                 |
+/// | 5          | `&`                 |
+/// | 6          | `==` `!=`           |
+/// | 7          | `<` `<=` `>` `>=`   |
+/// | 8          | `<<` `>>`           |
+/// | 9          | `+` `-`             |
+/// | 10         | `*` `/` `%`         |
+///
+/// These are the levels C uses. Prefix `-`, `!` and `~` bind tighter than
+/// any binary operator, indexing with `[]` binds tighter still, and
+/// parentheses can be used to group anything.
+fn binary_precedence(token: &Token) -> Option<u8> {
+    match token {
+        Token::DoublePipe => Some(1),
+        Token::DoubleAmpersand => Some(2),
+        Token::Pipe => Some(3),
+        Token::Caret => Some(4),
+        Token::Ampersand => Some(5),
+        Token::IsEqual | Token::DoesNotEqual => Some(6),
+        Token::LessThan | Token::LessThanOrEqual | Token::GreaterThan | Token::GreaterThanOrEqual => Some(7),
+        Token::ShiftLeft | Token::ShiftRight => Some(8),
+        Token::Plus | Token::Minus => Some(9),
+        Token::Star | Token::Slash | Token::Percent => Some(10),
+        _ => None,
+    }
 }
 
-pub fn parse(token_iter: &mut TokenIterator) -> Result<Expression, SyntaxError> {
-    let mut exp = get_value(token_iter.next())?;
+fn binary_expression(token: &Token, left: Expression, right: Expression) -> ExpressionKind {
+    let left = Box::new(left);
+    let right = Box::new(right);
 
-    while let Some(token) = token_iter.peek() {
-        match token {
-            Token::Plus => {
-                token_iter.next();
-                let right = parse(token_iter)?;
-                exp = Expression::Addition {
-                    left: Box::new(exp),
-                    right: Box::new(right),
-                };
-            },
-        Token::DoesNotEqual => {
+    match token {
+        Token::DoesNotEqual => ExpressionKind::NotComparison { left, right },
+        Token::IsEqual => ExpressionKind::EqualComparison { left, right },
+        Token::LessThan => ExpressionKind::LessThanComparison { left, right },
+        Token::LessThanOrEqual => ExpressionKind::LessThanOrEqualComparison { left, right },
+        Token::GreaterThan => ExpressionKind::GreaterThanComparison { left, right },
+        Token::GreaterThanOrEqual => ExpressionKind::GreaterThanOrEqualComparison { left, right },
+        Token::Plus => ExpressionKind::Addition { left, right },
+        Token::Minus => ExpressionKind::Subtraction { left, right },
+        Token::Star => ExpressionKind::Multiplication { left, right },
+        Token::Slash => ExpressionKind::Division { left, right },
+        Token::Percent => ExpressionKind::Remainder { left, right },
+        Token::DoubleAmpersand => ExpressionKind::LogicalAnd { left, right },
+        Token::DoublePipe => ExpressionKind::LogicalOr { left, right },
+        Token::Ampersand => ExpressionKind::BitwiseAnd { left, right },
+        Token::Pipe => ExpressionKind::BitwiseOr { left, right },
+        Token::Caret => ExpressionKind::BitwiseXor { left, right },
+        Token::ShiftLeft => ExpressionKind::ShiftLeft { left, right },
+        Token::ShiftRight => ExpressionKind::ShiftRight { left, right },
+        _ => unreachable!("{:?} is not a binary operator", token),
+    }
+}
+
+fn parse_primary(token_iter: &mut TokenIterator) -> Result<Expression, SyntaxError> {
+    let start = token_iter.peek_span();
+
+    let kind = match token_iter.peek() {
+        Some(Token::Number(num)) => {
             token_iter.next();
-            let right = parse(token_iter)?;
-            exp = Expression::NotComparison {
-                left: Box::new(exp),
-                right: Box::new(right),
+            ExpressionKind::NumberLiteral(*num)
+        },
+        Some(Token::QuotedString(value)) => {
+            token_iter.next();
+            ExpressionKind::StringLiteral(value.clone())
+        },
+        Some(Token::Identifier(value)) => {
+            token_iter.next();
+            match token_iter.peek() {
+                Some(Token::OpenParen) => ExpressionKind::FunctionCall {
+                    identifier: value.clone(),
+                    arguments: parse_call_arguments(token_iter)?,
+                },
+                _ => ExpressionKind::Variable(value.clone()),
             }
         },
-        Token::OpenBracket => {
+        Some(Token::OpenParen) => {
             token_iter.next();
             let inner = parse(token_iter)?;
-            validate_syntax!(token_iter.next(), Some(Token::CloseBracket))?;
-            exp = Expression::Lookup {
-                base: Box::new(exp),
-                index: Box::new(inner),
-            }
+            validate_syntax!(token_iter, Token::CloseParen)?;
+            inner.kind
         },
-        _ => break,
+        found => return Err(SyntaxError::unexpected(found, vec!["expression".to_string()], token_iter.peek_span())),
+    };
+
+    Ok(Expression {
+        kind,
+        span: start.to(token_iter.last_span()),
+    })
+}
+
+/// Parse the parenthesized, comma separated argument list of a function call
+pub fn parse_call_arguments(token_iter: &mut TokenIterator) -> Result<Vec<Expression>, SyntaxError> {
+    validate_syntax!(token_iter, Token::OpenParen)?;
+
+    let mut arguments = vec![];
+    if let Some(Token::CloseParen) = token_iter.peek() {
+        token_iter.next();
+        return Ok(arguments);
+    }
+
+    loop {
+        arguments.push(parse(token_iter)?);
+        if let Some(Token::CloseParen) = token_iter.peek() {
+            token_iter.next();
+            return Ok(arguments);
         }
+        validate_syntax!(token_iter, Token::Comma | Token::CloseParen)?;
+    }
+}
+
+/// Parse a primary expression along with any indexing that follows it
+pub fn parse_postfix(token_iter: &mut TokenIterator) -> Result<Expression, SyntaxError> {
+    let mut exp = parse_primary(token_iter)?;
+
+    while let Some(Token::OpenBracket) = token_iter.peek() {
+        token_iter.next();
+        let index = parse(token_iter)?;
+        validate_syntax!(token_iter, Token::CloseBracket)?;
+        exp = Expression {
+            span: exp.span.to(token_iter.last_span()),
+            kind: ExpressionKind::Lookup {
+                base: Box::new(exp),
+                index: Box::new(index),
+            },
+        };
+    }
+
+    Ok(exp)
+}
+
+fn parse_unary(token_iter: &mut TokenIterator) -> Result<Expression, SyntaxError> {
+    let start = token_iter.peek_span();
+
+    let operator = match token_iter.peek() {
+        Some(operator @ (Token::Minus | Token::Bang | Token::Tilde)) => operator,
+        _ => return parse_postfix(token_iter),
+    };
+    token_iter.next();
+
+    let operand = Box::new(parse_unary(token_iter)?);
+    Ok(Expression {
+        span: start.to(operand.span),
+        kind: match operator {
+            Token::Minus => ExpressionKind::Negation { operand },
+            Token::Bang => ExpressionKind::LogicalNot { operand },
+            _ => ExpressionKind::BitwiseNot { operand },
+        },
+    })
+}
+
+/// Parse operators binding tighter than `min_precedence` by precedence
+/// climbing
+fn parse_binary(token_iter: &mut TokenIterator, min_precedence: u8) -> Result<Expression, SyntaxError> {
+    let mut exp = parse_unary(token_iter)?;
+
+    while let Some(token) = token_iter.peek() {
+        let precedence = match binary_precedence(token) {
+            Some(precedence) if precedence > min_precedence => precedence,
+            _ => break,
+        };
+        token_iter.next();
+
+        // Only letting tighter operators into the right hand side is what
+        // makes operators on the same level left associative
+        let right = parse_binary(token_iter, precedence)?;
+        exp = Expression {
+            span: exp.span.to(right.span),
+            kind: binary_expression(token, exp, right),
+        };
     }
-    
+
     Ok(exp)
 }
 
+pub fn parse(token_iter: &mut TokenIterator) -> Result<Expression, SyntaxError> {
+    parse_binary(token_iter, 0)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
+    use super::super::super::tokenizer;
+    use ExpressionKind::*;
+
+    fn boxed(kind: ExpressionKind) -> Box<Expression> {
+        Box::new(Expression::unspanned(kind))
+    }
 
     #[test]
     fn value_expressions() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Number(5),
-            ].iter().peekable()),
-            Ok(Expression::NumberLiteral(5))
+            ]))),
+            Ok(Expression::unspanned(NumberLiteral(5)))
         );
         assert_eq!(
-            parse(&mut vec![
-                Token::QuotedString("Test".to_string()),
-            ].iter().peekable()),
-            Ok(Expression::StringLiteral("Test".to_string()))
+            parse(&mut TokenIterator::new(&unspanned(vec![
+                Token::QuotedString(b"Test".to_vec()),
+            ]))),
+            Ok(Expression::unspanned(StringLiteral(b"Test".to_vec())))
         );
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Identifier("foobar".to_string()),
-            ].iter().peekable()),
-            Ok(Expression::Variable("foobar".to_string()))
+            ]))),
+            Ok(Expression::unspanned(Variable("foobar".to_string())))
         );
     }
 
     #[test]
     fn lookup_expressions() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Identifier("foobar".to_string()),
                 Token::OpenBracket,
                 Token::Number(5),
                 Token::CloseBracket,
-            ].iter().peekable()),
-            Ok(Expression::Lookup{
-                base: Box::new(Expression::Variable("foobar".to_string())),
-                index: Box::new(Expression::NumberLiteral(5)),
-            })
+            ]))),
+            Ok(Expression::unspanned(Lookup{
+                base: boxed(Variable("foobar".to_string())),
+                index: boxed(NumberLiteral(5)),
+            }))
         );
     }
 
     #[test]
     fn not_comparison_expressions() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Number(5),
                 Token::DoesNotEqual,
                 Token::Number(1),
-            ].iter().peekable()),
-            Ok(Expression::NotComparison{
-                left: Box::new(Expression::NumberLiteral(5)),
-                right: Box::new(Expression::NumberLiteral(1)),
-            })
+            ]))),
+            Ok(Expression::unspanned(NotComparison{
+                left: boxed(NumberLiteral(5)),
+                right: boxed(NumberLiteral(1)),
+            }))
         );
     }
 
     #[test]
     fn addition_expressions() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Number(5),
                 Token::Plus,
                 Token::Number(1),
-            ].iter().peekable()),
-            Ok(Expression::Addition{
-                left: Box::new(Expression::NumberLiteral(5)),
-                right: Box::new(Expression::NumberLiteral(1)),
-            })
+            ]))),
+            Ok(Expression::unspanned(Addition{
+                left: boxed(NumberLiteral(5)),
+                right: boxed(NumberLiteral(1)),
+            }))
         );
     }
 
@@ -141,7 +381,7 @@ mod tests {
     fn complex_expressions() {
         // foobar[i + 1] != 5 + 4
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Identifier("foobar".to_string()),
                 Token::OpenBracket,
                 Token::Identifier("i".to_string()),
@@ -152,20 +392,308 @@ mod tests {
                 Token::Number(5),
                 Token::Plus,
                 Token::Number(4)
-            ].iter().peekable()),
-            Ok(Expression::NotComparison{
-                left: Box::new(Expression::Lookup{
-                    base: Box::new(Expression::Variable("foobar".to_string())),
-                    index: Box::new(Expression::Addition {
-                        left: Box::new(Expression::Variable("i".to_string())),
-                        right: Box::new(Expression::NumberLiteral(1))
+            ]))),
+            Ok(Expression::unspanned(NotComparison{
+                left: boxed(Lookup{
+                    base: boxed(Variable("foobar".to_string())),
+                    index: boxed(Addition {
+                        left: boxed(Variable("i".to_string())),
+                        right: boxed(NumberLiteral(1))
                     }),
                 }),
-                right: Box::new(Expression::Addition{
-                    left: Box::new(Expression::NumberLiteral(5)),
-                    right: Box::new(Expression::NumberLiteral(4)),
+                right: boxed(Addition{
+                    left: boxed(NumberLiteral(5)),
+                    right: boxed(NumberLiteral(4)),
+                }),
+            }))
+        );
+    }
+
+    fn parse_code(code: &str) -> Result<Expression, SyntaxError> {
+        let tokens = tokenizer::tokenize(code.to_string()).unwrap();
+        parse(&mut TokenIterator::new(&unspanned(tokens.into_iter().map(|t| t.node).collect())))
+    }
+
+    fn variable(name: &str) -> Box<Expression> {
+        boxed(Variable(name.to_string()))
+    }
+
+    #[test]
+    fn addition_is_left_associative() {
+        assert_eq!(
+            parse_code("a + b + c"),
+            Ok(Expression::unspanned(Addition {
+                left: boxed(Addition {
+                    left: variable("a"),
+                    right: variable("b"),
+                }),
+                right: variable("c"),
+            }))
+        );
+    }
+
+    #[test]
+    fn addition_binds_tighter_than_comparison() {
+        assert_eq!(
+            parse_code("a != b + 1"),
+            Ok(Expression::unspanned(NotComparison {
+                left: variable("a"),
+                right: boxed(Addition {
+                    left: variable("b"),
+                    right: boxed(NumberLiteral(1)),
+                }),
+            }))
+        );
+        assert_eq!(
+            parse_code("a + b != c"),
+            Ok(Expression::unspanned(NotComparison {
+                left: boxed(Addition {
+                    left: variable("a"),
+                    right: variable("b"),
+                }),
+                right: variable("c"),
+            }))
+        );
+    }
+
+    #[test]
+    fn parentheses_group_expressions() {
+        assert_eq!(
+            parse_code("a + (b + c)"),
+            Ok(Expression::unspanned(Addition {
+                left: variable("a"),
+                right: boxed(Addition {
+                    left: variable("b"),
+                    right: variable("c"),
+                }),
+            }))
+        );
+        assert_eq!(
+            parse_code("((a != b)) + s[(i)]"),
+            Ok(Expression::unspanned(Addition {
+                left: boxed(NotComparison {
+                    left: variable("a"),
+                    right: variable("b"),
+                }),
+                right: boxed(Lookup {
+                    base: variable("s"),
+                    index: variable("i"),
                 }),
+            }))
+        );
+    }
+
+    #[test]
+    fn parenthesized_spans_include_the_parentheses() {
+        let tokens = tokenizer::tokenize("(a + b) + c".to_string()).unwrap();
+        let exp = parse(&mut TokenIterator::new(&tokens)).unwrap();
+
+        assert_eq!(exp.span, Span { start: 0, end: 11, line: 1, column: 1 });
+        match exp.kind {
+            Addition { left, .. } => assert_eq!(left.span, Span { start: 0, end: 7, line: 1, column: 1 }),
+            _ => panic!("Expected an addition"),
+        }
+    }
+
+    #[test]
+    fn unclosed_parentheses() {
+        assert_eq!(
+            parse_code("(a + b"),
+            Err(SyntaxError::UnexpectedEndOfInput {
+                expected: vec!["`)`".to_string()],
+                span: Span::default(),
             })
         );
     }
+
+    #[test]
+    fn arithmetic_precedence() {
+        // a - b * c % d - e  =>  (a - ((b * c) % d)) - e
+        assert_eq!(
+            parse_code("a - b * c % d - e"),
+            Ok(Expression::unspanned(Subtraction {
+                left: boxed(Subtraction {
+                    left: variable("a"),
+                    right: boxed(Remainder {
+                        left: boxed(Multiplication {
+                            left: variable("b"),
+                            right: variable("c"),
+                        }),
+                        right: variable("d"),
+                    }),
+                }),
+                right: variable("e"),
+            }))
+        );
+        assert_eq!(
+            parse_code("a / b / c"),
+            Ok(Expression::unspanned(Division {
+                left: boxed(Division {
+                    left: variable("a"),
+                    right: variable("b"),
+                }),
+                right: variable("c"),
+            }))
+        );
+    }
+
+    #[test]
+    fn negation_binds_tighter_than_binary_operators() {
+        assert_eq!(
+            parse_code("-a * b"),
+            Ok(Expression::unspanned(Multiplication {
+                left: boxed(Negation {
+                    operand: variable("a"),
+                }),
+                right: variable("b"),
+            }))
+        );
+        assert_eq!(
+            parse_code("a - -s[0]"),
+            Ok(Expression::unspanned(Subtraction {
+                left: variable("a"),
+                right: boxed(Negation {
+                    operand: boxed(Lookup {
+                        base: variable("s"),
+                        index: boxed(NumberLiteral(0)),
+                    }),
+                }),
+            }))
+        );
+    }
+
+    #[test]
+    fn comparison_precedence() {
+        // a == b < c + 1 != d >= e  =>  (a == (b < (c + 1))) != (d >= e)
+        assert_eq!(
+            parse_code("a == b < c + 1 != d >= e"),
+            Ok(Expression::unspanned(NotComparison {
+                left: boxed(EqualComparison {
+                    left: variable("a"),
+                    right: boxed(LessThanComparison {
+                        left: variable("b"),
+                        right: boxed(Addition {
+                            left: variable("c"),
+                            right: boxed(NumberLiteral(1)),
+                        }),
+                    }),
+                }),
+                right: boxed(GreaterThanOrEqualComparison {
+                    left: variable("d"),
+                    right: variable("e"),
+                }),
+            }))
+        );
+        assert_eq!(
+            parse_code("a <= b > c"),
+            Ok(Expression::unspanned(GreaterThanComparison {
+                left: boxed(LessThanOrEqualComparison {
+                    left: variable("a"),
+                    right: variable("b"),
+                }),
+                right: variable("c"),
+            }))
+        );
+    }
+
+    #[test]
+    fn logical_and_bitwise_precedence() {
+        // a || b && c | d ^ e & f == g  =>  a || (b && (c | (d ^ (e & (f == g)))))
+        assert_eq!(
+            parse_code("a || b && c | d ^ e & f == g"),
+            Ok(Expression::unspanned(LogicalOr {
+                left: variable("a"),
+                right: boxed(LogicalAnd {
+                    left: variable("b"),
+                    right: boxed(BitwiseOr {
+                        left: variable("c"),
+                        right: boxed(BitwiseXor {
+                            left: variable("d"),
+                            right: boxed(BitwiseAnd {
+                                left: variable("e"),
+                                right: boxed(EqualComparison {
+                                    left: variable("f"),
+                                    right: variable("g"),
+                                }),
+                            }),
+                        }),
+                    }),
+                }),
+            }))
+        );
+        // a < b << c + d  =>  a < (b << (c + d))
+        assert_eq!(
+            parse_code("a < b << c + d"),
+            Ok(Expression::unspanned(LessThanComparison {
+                left: variable("a"),
+                right: boxed(ShiftLeft {
+                    left: variable("b"),
+                    right: boxed(Addition {
+                        left: variable("c"),
+                        right: variable("d"),
+                    }),
+                }),
+            }))
+        );
+    }
+
+    #[test]
+    fn prefix_operators_nest() {
+        assert_eq!(
+            parse_code("!~-a >> b"),
+            Ok(Expression::unspanned(ShiftRight {
+                left: boxed(LogicalNot {
+                    operand: boxed(BitwiseNot {
+                        operand: boxed(Negation {
+                            operand: variable("a"),
+                        }),
+                    }),
+                }),
+                right: variable("b"),
+            }))
+        );
+    }
+
+    #[test]
+    fn call_expressions() {
+        assert_eq!(
+            parse_code("strlen(s) + 1 * f()"),
+            Ok(Expression::unspanned(Addition {
+                left: boxed(FunctionCall {
+                    identifier: "strlen".to_string(),
+                    arguments: vec![*variable("s")],
+                }),
+                right: boxed(Multiplication {
+                    left: boxed(NumberLiteral(1)),
+                    right: boxed(FunctionCall {
+                        identifier: "f".to_string(),
+                        arguments: vec![],
+                    }),
+                }),
+            }))
+        );
+    }
+
+    #[test]
+    fn call_arguments() {
+        assert_eq!(
+            parse_code("f(a, 1 + 2, g())"),
+            Ok(Expression::unspanned(FunctionCall {
+                identifier: "f".to_string(),
+                arguments: vec![
+                    *variable("a"),
+                    Expression::unspanned(Addition {
+                        left: boxed(NumberLiteral(1)),
+                        right: boxed(NumberLiteral(2)),
+                    }),
+                    Expression::unspanned(FunctionCall {
+                        identifier: "g".to_string(),
+                        arguments: vec![],
+                    }),
+                ],
+            }))
+        );
+        assert!(parse_code("f(a,)").is_err());
+        assert!(parse_code("f(a b)").is_err());
+    }
 }
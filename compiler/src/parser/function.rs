@@ -3,55 +3,61 @@ use super::*;
 #[derive(Debug, PartialEq, Eq)]
 pub struct Function {
     pub identifier: String,
-    pub argument: Option<String>,
-    pub statements: Vec::<statement::Statement>
+    pub parameters: Vec<Spanned<String>>,
+    pub statements: Vec::<statement::Statement>,
+    pub span: Span,
 }
 
 pub fn parse(token_iter: &mut TokenIterator) -> Result<Function, SyntaxError> {
-    validate_syntax!(token_iter.next(), Some(Token::Function))?;
-    let identifier = validate_syntax!(token_iter.next(), Some(Token::Identifier(x)) => x)?;
-    validate_syntax!(token_iter.next(), Some(Token::OpenParen))?;
+    let start = token_iter.peek_span();
 
-    let mut argument = None;
+    validate_syntax!(token_iter, Token::Function)?;
+    let identifier = validate_syntax!(token_iter, Token::Identifier(x) => x)?;
+    validate_syntax!(token_iter, Token::OpenParen)?;
+
+    let mut parameters = vec![];
 
     match token_iter.peek() {
         Some(Token::CloseParen) => {
             token_iter.next();
         }
-        Some(Token::Identifier(name)) => {
-            argument = Some(name.clone());
-            token_iter.next();
-            validate_syntax!(token_iter.next(), Some(Token::CloseParen))?;
+        Some(Token::Identifier(_)) => loop {
+            let name = validate_syntax!(token_iter, Token::Identifier(x) => x)?;
+            parameters.push(Spanned { node: name.clone(), span: token_iter.last_span() });
+            if let Some(Token::CloseParen) = token_iter.peek() {
+                token_iter.next();
+                break;
+            }
+            validate_syntax!(token_iter, Token::Comma | Token::CloseParen)?;
         },
-        _ => return Err(SyntaxError::UnexpectedToken)
+        found => return Err(SyntaxError::unexpected(
+            found,
+            vec![TokenKind::Identifier.describe().to_string(), TokenKind::CloseParen.describe().to_string()],
+            token_iter.peek_span(),
+        ))
     }
 
-    validate_syntax!(token_iter.next(), Some(Token::OpenBrace))?;
-
-    let mut statements = vec![];
-    while let Some(statement) = statement::parse(token_iter)? {
-        statements.push(statement);
-    }
-
-    validate_syntax!(token_iter.next(), Some(Token::CloseBrace))?;
+    let statements = statement::parse_block(token_iter)?;
 
     Ok(Function {
         identifier: identifier.clone(),
-        argument,
+        parameters,
         statements,
+        span: start.to(token_iter.last_span()),
     })
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use statement::Statement;
-    use expression::Expression;
+    use super::super::super::tokenizer;
+    use statement::{ Statement, StatementKind };
+    use expression::{ Expression, ExpressionKind };
 
     #[test]
     fn define_function() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Function,
                 Token::Identifier("main".to_string()),
                 Token::OpenParen,
@@ -70,20 +76,44 @@ mod tests {
                 Token::Semicolon,
 
                 Token::CloseBrace,
-            ].iter().peekable()),
+            ]))),
             Ok(Function {
                 identifier: "main".to_string(),
-                argument: None,
+                parameters: vec![],
                 statements: vec![
-                    Statement::Assignment {
+                    Statement::unspanned(StatementKind::Declaration {
                         identifier: "foo".to_string(),
-                        value: Expression::NumberLiteral(5),
-                    },
-                    Statement::FunctionCall {
+                        value: Expression::unspanned(ExpressionKind::NumberLiteral(5)),
+                    }),
+                    Statement::unspanned(StatementKind::FunctionCall {
                         identifier: "bar".to_string(),
-                        param: None,
-                    }
+                        arguments: vec![],
+                    })
                 ],
+                span: Span::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn define_function_with_parameters() {
+        let tokens = tokenizer::tokenize("fn add(a, b, c) { }".to_string()).unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).map(|f| f.parameters),
+            Ok(vec![
+                Spanned { node: "a".to_string(), span: Span { start: 7, end: 8, line: 1, column: 8 } },
+                Spanned { node: "b".to_string(), span: Span { start: 10, end: 11, line: 1, column: 11 } },
+                Spanned { node: "c".to_string(), span: Span { start: 13, end: 14, line: 1, column: 14 } },
+            ])
+        );
+
+        let tokens = tokenizer::tokenize("fn add(a b) { }".to_string()).unwrap();
+        assert_eq!(
+            parse(&mut TokenIterator::new(&tokens)).map(|f| f.parameters),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Identifier("b".to_string()),
+                expected: vec!["`,`".to_string(), "`)`".to_string()],
+                span: Span { start: 9, end: 10, line: 1, column: 10 },
             })
         );
     }
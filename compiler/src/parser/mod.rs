@@ -1,11 +1,193 @@
-use super::tokenizer::Token;
+use super::diagnostics::Diagnostic;
+use super::span::{ Span, Spanned };
+use super::tokenizer::{ Token, TokenKind };
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum SyntaxError {
-    UnexpectedToken,
+    UnexpectedToken {
+        found: Token,
+        expected: Vec<String>,
+        span: Span,
+    },
+    UnexpectedEndOfInput {
+        expected: Vec<String>,
+        span: Span,
+    },
+    /// Assigning to something other than a variable, like `s[0] = 5;`
+    InvalidAssignmentTarget(Span),
+    /// A `let` followed by something other than a variable name
+    InvalidDeclarationTarget(Span),
+    /// A `let` in the step of a `for`, which would declare a new variable
+    /// on every iteration
+    DeclarationInForStep(Span),
 }
 
-type TokenIterator<'a> = std::iter::Peekable<std::slice::Iter<'a, Token>>;
+impl SyntaxError {
+    /// The error for running into `found` (or the end of the input) when one
+    /// of the `expected` tokens should have been there
+    pub fn unexpected(found: Option<&Token>, expected: Vec<String>, span: Span) -> SyntaxError {
+        match found {
+            Some(token) => SyntaxError::UnexpectedToken {
+                found: token.clone(),
+                expected,
+                span,
+            },
+            None => SyntaxError::UnexpectedEndOfInput {
+                expected,
+                span,
+            },
+        }
+    }
+
+    pub fn span(&self) -> Span {
+        match self {
+            SyntaxError::UnexpectedToken { span, .. } => *span,
+            SyntaxError::UnexpectedEndOfInput { span, .. } => *span,
+            SyntaxError::InvalidAssignmentTarget(span) => *span,
+            SyntaxError::InvalidDeclarationTarget(span) => *span,
+            SyntaxError::DeclarationInForStep(span) => *span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = match self {
+            SyntaxError::UnexpectedToken { found, expected, .. } => {
+                let expected = join_expected(expected);
+                Diagnostic::error(format!("expected {}, found {}", expected, found))
+                    .with_label(format!("expected {}", expected))
+            },
+            SyntaxError::UnexpectedEndOfInput { expected, .. } => Diagnostic::error("unexpected end of input")
+                .with_label(format!("expected {}", join_expected(expected))),
+            SyntaxError::InvalidAssignmentTarget(_) => Diagnostic::error("invalid assignment target")
+                .with_label("cannot assign to this expression")
+                .with_note("only variables can be assigned to"),
+            SyntaxError::InvalidDeclarationTarget(_) => Diagnostic::error("invalid declaration")
+                .with_label("expected a variable name")
+                .with_note("`let` declares a single variable, like `let x = 1;`"),
+            SyntaxError::DeclarationInForStep(_) => Diagnostic::error("declarations aren't allowed in the step of a `for`")
+                .with_label("declares a variable")
+                .with_note("declare the variable before the loop or in its initializer"),
+        };
+
+        diagnostic.with_span(self.span())
+    }
+}
+
+/// Join descriptions into "`a`", "`a` or `b`", "`a`, `b` or `c`"...
+fn join_expected(expected: &[String]) -> String {
+    let mut unique: Vec<&str> = vec![];
+    for description in expected {
+        if !unique.contains(&description.as_str()) {
+            unique.push(description);
+        }
+    }
+
+    match unique.split_last() {
+        None => "something else".to_string(),
+        Some((last, [])) => last.to_string(),
+        Some((last, rest)) => format!("{} or {}", rest.join(", "), last),
+    }
+}
+
+/// A cursor over the tokenizer's output. Along with handing out tokens it
+/// remembers where they came from so the parser can attach spans to the
+/// nodes it builds and the errors it reports, and it collects the errors the
+/// parser has recovered from.
+pub struct TokenIterator<'a> {
+    tokens: &'a [Spanned<Token>],
+    position: usize,
+    last_span: Span,
+    end_of_input: Span,
+    errors: Vec<SyntaxError>,
+}
+
+impl<'a> TokenIterator<'a> {
+    pub fn new(tokens: &'a [Spanned<Token>]) -> TokenIterator<'a> {
+        let end_of_input = match tokens.last() {
+            Some(token) => Span {
+                start: token.span.end,
+                end: token.span.end,
+                line: token.span.line,
+                column: token.span.column + (token.span.end - token.span.start),
+            },
+            None => Span { start: 0, end: 0, line: 1, column: 1 },
+        };
+
+        TokenIterator {
+            tokens,
+            position: 0,
+            last_span: end_of_input,
+            end_of_input,
+            errors: vec![],
+        }
+    }
+
+    pub fn peek(&self) -> Option<&'a Token> {
+        self.tokens.get(self.position).map(|t| &t.node)
+    }
+
+    pub fn next(&mut self) -> Option<&'a Token> {
+        match self.tokens.get(self.position) {
+            Some(token) => {
+                self.position += 1;
+                self.last_span = token.span;
+                Some(&token.node)
+            },
+            None => {
+                self.last_span = self.end_of_input;
+                None
+            }
+        }
+    }
+
+    /// The span of the token `peek` would return, or of the end of the input
+    pub fn peek_span(&self) -> Span {
+        self.tokens.get(self.position).map_or(self.end_of_input, |t| t.span)
+    }
+
+    /// The span of the token most recently returned by `next`
+    pub fn last_span(&self) -> Span {
+        self.last_span
+    }
+
+    /// Record an error the parser is going to recover from
+    pub fn report(&mut self, error: SyntaxError) {
+        self.errors.push(error);
+    }
+
+    /// Skip ahead to somewhere parsing can sensibly pick back up after an
+    /// error: just past the `;` ending the current statement, just past the
+    /// `}` ending a block the bad statement opened, or right before a `}` or
+    /// `fn` that ends the enclosing block or starts the next function.
+    pub fn synchronize(&mut self) {
+        let mut depth = 0;
+
+        while let Some(token) = self.peek() {
+            match token {
+                Token::Function => return,
+                Token::CloseBrace if depth == 0 => return,
+                Token::CloseBrace => {
+                    self.next();
+                    depth -= 1;
+                    if depth == 0 {
+                        return;
+                    }
+                },
+                Token::OpenBrace => {
+                    self.next();
+                    depth += 1;
+                },
+                Token::Semicolon if depth == 0 => {
+                    self.next();
+                    return;
+                },
+                _ => {
+                    self.next();
+                },
+            }
+        }
+    }
+}
 
 #[macro_use]
 mod validate;
@@ -20,36 +202,66 @@ pub struct Program {
     pub statements: Vec<statement::Statement>,
 }
 
-pub fn parse(tokens: Vec<Token>) -> Result<Program, SyntaxError> {
-    let mut token_iter = tokens.iter().peekable();
+/// Parse every function and top level statement in `tokens`. Rather than
+/// stopping at the first syntax error the parser skips past it and carries
+/// on, so along with whatever parts of the program could be parsed this
+/// returns every error in the file.
+pub fn parse(tokens: Vec<Spanned<Token>>) -> (Program, Vec<SyntaxError>) {
+    let mut token_iter = TokenIterator::new(&tokens);
 
     let mut functions = vec![];
     let mut statements = vec![];
 
     while let Some(token) = token_iter.peek() {
-        if let Token::Function = token {
-            let function = function::parse(&mut token_iter)?;
-            functions.push(function);
-        } else if let Some(statement) = statement::parse(&mut token_iter)? {
-            statements.push(statement)
-        } else {
-            return Err(SyntaxError::UnexpectedToken);
+        let position = token_iter.position;
+
+        let result = match token {
+            Token::Function => function::parse(&mut token_iter).map(|function| functions.push(function)),
+            _ => statement::parse(&mut token_iter).and_then(|statement| match statement {
+                Some(statement) => {
+                    statements.push(statement);
+                    Ok(())
+                },
+                None => Err(SyntaxError::unexpected(
+                    token_iter.peek(),
+                    vec![TokenKind::Function.describe().to_string(), "statement".to_string()],
+                    token_iter.peek_span(),
+                )),
+            }),
+        };
+
+        if let Err(e) = result {
+            token_iter.report(e);
+            token_iter.synchronize();
+
+            // Always make progress, even when stuck on a stray `}`
+            if token_iter.position == position {
+                token_iter.next();
+            }
         }
     }
 
-    Ok(Program {
+    let program = Program {
         functions,
         statements,
-    })
+    };
+
+    (program, token_iter.errors)
+}
+
+/// Wrap bare tokens with empty spans so tests can build token streams by hand
+#[cfg(test)]
+fn unspanned(tokens: Vec<Token>) -> Vec<Spanned<Token>> {
+    tokens.into_iter().map(|node| Spanned { node, span: Span::default() }).collect()
 }
 
 #[cfg(test)]
 mod tests {
     use super::super::tokenizer;
     use super::*;
-    use expression::Expression;
+    use expression::{ Expression, ExpressionKind };
     use function::Function;
-    use statement::Statement;
+    use statement::{ Statement, StatementKind };
 
     #[test]
     fn parse_codeblock() {
@@ -62,22 +274,155 @@ mod tests {
         "#
         .to_string();
 
+        let tokens = tokenizer::tokenize(code).unwrap();
+        let tokens = unspanned(tokens.into_iter().map(|t| t.node).collect());
+
         assert_eq!(
-            parse(tokenizer::tokenize(code).unwrap()),
-            Ok(Program {
-                statements: vec![Statement::Assignment {
+            parse(tokens),
+            (Program {
+                statements: vec![Statement::unspanned(StatementKind::Declaration {
                     identifier: "hello_world".to_string(),
-                    value: Expression::StringLiteral("Hello, World!".to_string()),
-                }],
+                    value: Expression::unspanned(ExpressionKind::StringLiteral(b"Hello, World!".to_vec())),
+                })],
                 functions: vec![Function {
                     identifier: "main".to_string(),
-                    argument: None,
-                    statements: vec![Statement::Assignment {
+                    parameters: vec![],
+                    statements: vec![Statement::unspanned(StatementKind::Declaration {
                         identifier: "i".to_string(),
-                        value: Expression::NumberLiteral(0),
-                    },],
+                        value: Expression::unspanned(ExpressionKind::NumberLiteral(0)),
+                    }),],
+                    span: Span::default(),
                 }],
-            })
+            }, vec![])
         );
     }
+
+    #[test]
+    fn attaches_spans_to_nodes() {
+        let code = "fn main() {\n    let i = 1 + 2;\n}".to_string();
+        let (program, _) = parse(tokenizer::tokenize(code).unwrap());
+
+        let function = &program.functions[0];
+        assert_eq!(function.span, Span { start: 0, end: 32, line: 1, column: 1 });
+
+        let statement = &function.statements[0];
+        assert_eq!(statement.span, Span { start: 16, end: 30, line: 2, column: 5 });
+
+        match &statement.kind {
+            StatementKind::Declaration { value, .. } => {
+                assert_eq!(value.span, Span { start: 24, end: 29, line: 2, column: 13 });
+            },
+            _ => panic!("Expected a declaration"),
+        }
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        let code = "fn main() {\n    let = 5;\n}".to_string();
+
+        assert_eq!(
+            parse(tokenizer::tokenize(code).unwrap()).1,
+            vec![SyntaxError::UnexpectedToken {
+                found: Token::Equals,
+                expected: vec!["expression".to_string()],
+                span: Span { start: 20, end: 21, line: 2, column: 9 },
+            }]
+        );
+    }
+
+    #[test]
+    fn reports_end_of_input_position() {
+        let code = "fn main() {".to_string();
+
+        assert_eq!(
+            parse(tokenizer::tokenize(code).unwrap()).1,
+            vec![SyntaxError::UnexpectedEndOfInput {
+                expected: vec!["`}`".to_string()],
+                span: Span { start: 11, end: 11, line: 1, column: 12 },
+            }]
+        );
+    }
+
+    #[test]
+    fn describes_what_was_expected() {
+        let code = "fn main() {\n    print(1 + 2]\n}".to_string();
+        let (_, errors) = parse(tokenizer::tokenize(code).unwrap());
+
+        assert_eq!(errors[0].to_diagnostic().message, "expected `,` or `)`, found `]`");
+    }
+
+    #[test]
+    fn joins_expected_tokens() {
+        let expected = |list: &[&str]| join_expected(&list.iter().map(|s| s.to_string()).collect::<Vec<_>>());
+
+        assert_eq!(expected(&["`)`"]), "`)`");
+        assert_eq!(expected(&["`)`", "`,`"]), "`)` or `,`");
+        assert_eq!(expected(&["`)`", "`,`", "`)`", "`;`"]), "`)`, `,` or `;`");
+    }
+
+    #[test]
+    fn rejects_invalid_assignment_targets() {
+        let errors = |code: &str| parse(tokenizer::tokenize(code.to_string()).unwrap()).1;
+
+        assert_eq!(
+            errors("s[0] = 5;"),
+            vec![SyntaxError::InvalidAssignmentTarget(Span { start: 0, end: 4, line: 1, column: 1 })]
+        );
+        assert_eq!(
+            errors("f(1) = 5;"),
+            vec![SyntaxError::InvalidAssignmentTarget(Span { start: 0, end: 4, line: 1, column: 1 })]
+        );
+        assert_eq!(errors("s[0];")[0].to_diagnostic().message, "expected `=`, found `;`");
+        assert_eq!(errors("s;")[0].to_diagnostic().message, "expected `(` or `=`, found `;`");
+    }
+
+    #[test]
+    fn rejects_invalid_declaration_targets() {
+        let code = "let s[0] = 5;".to_string();
+
+        assert_eq!(
+            parse(tokenizer::tokenize(code).unwrap()).1,
+            vec![SyntaxError::InvalidDeclarationTarget(Span { start: 4, end: 8, line: 1, column: 5 })]
+        );
+    }
+
+    #[test]
+    fn recovers_from_multiple_errors() {
+        let code = r#"
+        fn first() {
+            let a = ;
+            print(a);
+            while (a != ) {
+                print(a);
+            }
+            let b = 1 2;
+        }
+
+        fn second(x y) {
+            print(x);
+        }
+
+        fn third() {
+            print(3;
+        }
+        "#.to_string();
+
+        let (program, errors) = parse(tokenizer::tokenize(code).unwrap());
+
+        let lines: Vec<usize> = errors.iter().map(|e| e.span().line).collect();
+        assert_eq!(lines, vec![3, 5, 8, 11, 16]);
+
+        let names: Vec<&str> = program.functions.iter().map(|f| &f.identifier[..]).collect();
+        assert_eq!(names, vec!["first", "third"]);
+        assert_eq!(program.functions[0].statements.len(), 1);
+    }
+
+    #[test]
+    fn skips_stray_tokens_at_top_level() {
+        let code = "} ] fn main() {}".to_string();
+        let (program, errors) = parse(tokenizer::tokenize(code).unwrap());
+
+        assert_eq!(errors.len(), 2);
+        assert_eq!(program.functions.len(), 1);
+    }
 }
@@ -2,119 +2,306 @@ use super::*;
 use super::super::tokenizer::Token;
 
 #[derive(Debug, PartialEq, Eq)]
-pub enum Statement {
+pub struct Statement {
+    pub kind: StatementKind,
+    pub span: Span,
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum StatementKind {
+    /// `let identifier = value;`
+    Declaration {
+        identifier: String,
+        value: expression::Expression,
+    },
+    /// `identifier = value;`
     Assignment {
         identifier: String,
         value: expression::Expression,
     },
+    Block {
+        statements: Vec::<Statement>,
+    },
     While {
         condition: expression::Expression,
         statements: Vec::<Statement>
     },
+    /// `for (initializer; condition; step) { statements }`, where a missing
+    /// condition is always true
+    For {
+        initializer: Option::<Box::<Statement>>,
+        condition: Option::<expression::Expression>,
+        step: Option::<Box::<Statement>>,
+        statements: Vec::<Statement>,
+    },
+    Break,
+    Continue,
+    /// `else if` is an `If` as the only statement of `else_statements`
+    If {
+        condition: expression::Expression,
+        statements: Vec::<Statement>,
+        else_statements: Vec::<Statement>,
+    },
     FunctionCall {
         identifier: String,
-        param: Option::<expression::Expression>
+        arguments: Vec::<expression::Expression>,
+    },
+    Return {
+        value: Option::<expression::Expression>,
+    },
+}
+
+#[cfg(test)]
+impl Statement {
+    pub fn unspanned(kind: StatementKind) -> Statement {
+        Statement { kind, span: Span::default() }
     }
 }
 
 pub fn parse(token_iter: &mut TokenIterator) -> Result<Option<Statement>, SyntaxError> {
-    match token_iter.peek() {
-        Some(Token::Let) => {
-            token_iter.next();
-            let identifier = validate_syntax!(token_iter.next(), Some(Token::Identifier(x)) => x)?;
-            validate_syntax!(token_iter.next(), Some(Token::Equals))?;
-            let value = expression::parse(token_iter)?;
-            validate_syntax!(token_iter.next(), Some(Token::Semicolon))?;
-            Ok(Some(Statement::Assignment {
-                identifier: identifier.clone(),
-                value,
-            }))
+    let start = token_iter.peek_span();
+
+    let kind = match token_iter.peek() {
+        Some(Token::Let | Token::Identifier(_)) => {
+            let kind = parse_simple(token_iter)?;
+            validate_syntax!(token_iter, Token::Semicolon)?;
+            kind
+        },
+        Some(Token::OpenBrace) => StatementKind::Block {
+            statements: parse_block(token_iter)?,
         },
         Some(Token::While) => {
             token_iter.next();
-            validate_syntax!(token_iter.next(), Some(Token::OpenParen))?;
+            validate_syntax!(token_iter, Token::OpenParen)?;
             let condition = expression::parse(token_iter)?;
-            validate_syntax!(token_iter.next(), Some(Token::CloseParen))?;
-            validate_syntax!(token_iter.next(), Some(Token::OpenBrace))?;
-            let mut statements = vec![];
-            while let Some(statement) = parse(token_iter)? {
-                statements.push(statement);
-            }
-            validate_syntax!(token_iter.next(), Some(Token::CloseBrace))?;
-            Ok(Some(Statement::While {
+            validate_syntax!(token_iter, Token::CloseParen)?;
+            let statements = parse_block(token_iter)?;
+            StatementKind::While {
                 condition,
                 statements
-            }))
+            }
+        },
+        Some(Token::For) => parse_for(token_iter)?,
+        Some(Token::If) => parse_if(token_iter)?,
+        Some(Token::Return) => {
+            token_iter.next();
+            let value = match token_iter.peek() {
+                Some(Token::Semicolon) => None,
+                _ => Some(expression::parse(token_iter)?),
+            };
+            validate_syntax!(token_iter, Token::Semicolon)?;
+            StatementKind::Return { value }
         },
-        Some(Token::Identifier(value)) => {
+        Some(Token::Break) => {
             token_iter.next();
-            validate_syntax!(token_iter.next(), Some(Token::OpenParen))?;
-            let mut param = None;
+            validate_syntax!(token_iter, Token::Semicolon)?;
+            StatementKind::Break
+        },
+        Some(Token::Continue) => {
+            token_iter.next();
+            validate_syntax!(token_iter, Token::Semicolon)?;
+            StatementKind::Continue
+        },
+        _ => return Ok(None)
+    };
 
-            match token_iter.peek() {
-                Some(Token::CloseParen) => {
+    Ok(Some(Statement {
+        kind,
+        span: start.to(token_iter.last_span()),
+    }))
+}
+
+/// Parse a declaration, assignment or call without its trailing `;`, as
+/// they're written in the header of a `for` loop
+fn parse_simple(token_iter: &mut TokenIterator) -> Result<StatementKind, SyntaxError> {
+    match token_iter.peek() {
+        Some(Token::Let) => {
+            token_iter.next();
+            let target = expression::parse(token_iter)?;
+            let identifier = match &target.kind {
+                expression::ExpressionKind::Variable(name) => name,
+                _ => return Err(SyntaxError::InvalidDeclarationTarget(target.span)),
+            };
+            validate_syntax!(token_iter, Token::Equals)?;
+            let value = expression::parse(token_iter)?;
+            Ok(StatementKind::Declaration {
+                identifier: identifier.clone(),
+                value,
+            })
+        },
+        Some(Token::Identifier(_)) => {
+            // Parsed as far as indexing goes, so a lookup being assigned to
+            // is reported as such rather than as an unexpected `[`
+            let target = expression::parse_postfix(token_iter)?;
+            match (target.kind, token_iter.peek()) {
+                (expression::ExpressionKind::Variable(identifier), Some(Token::Equals)) => {
                     token_iter.next();
-                }
-                _ => {
-                    let value = expression::parse(token_iter)?;
-                    param = Some(value);
-                    validate_syntax!(token_iter.next(), Some(Token::CloseParen))?;
-                }
+                    Ok(StatementKind::Assignment {
+                        identifier,
+                        value: expression::parse(token_iter)?,
+                    })
+                },
+                (_, Some(Token::Equals)) => Err(SyntaxError::InvalidAssignmentTarget(target.span)),
+                (expression::ExpressionKind::FunctionCall { identifier, arguments }, _) => {
+                    Ok(StatementKind::FunctionCall { identifier, arguments })
+                },
+                (expression::ExpressionKind::Variable(_), found) => Err(SyntaxError::unexpected(
+                    found,
+                    vec![TokenKind::OpenParen.describe().to_string(), TokenKind::Equals.describe().to_string()],
+                    token_iter.peek_span(),
+                )),
+                (_, found) => Err(SyntaxError::unexpected(
+                    found,
+                    vec![TokenKind::Equals.describe().to_string()],
+                    token_iter.peek_span(),
+                )),
             }
+        },
+        found => Err(SyntaxError::unexpected(
+            found,
+            vec![TokenKind::Let.describe().to_string(), TokenKind::Identifier.describe().to_string()],
+            token_iter.peek_span(),
+        )),
+    }
+}
 
-            validate_syntax!(token_iter.next(), Some(Token::Semicolon))?;
-            Ok(Some(Statement::FunctionCall {
-                identifier: value.clone(),
-                param,
-            }))
+/// Parse the `init`, `cond` or `step` part of a `for` header, any of which
+/// may be left empty, up to but not including `terminator`
+fn parse_for_clause(token_iter: &mut TokenIterator, terminator: &Token) -> Result<Option<Box<Statement>>, SyntaxError> {
+    if token_iter.peek() == Some(terminator) {
+        return Ok(None);
+    }
+
+    let start = token_iter.peek_span();
+    let kind = parse_simple(token_iter)?;
+    Ok(Some(Box::new(Statement {
+        kind,
+        span: start.to(token_iter.last_span()),
+    })))
+}
+
+fn parse_for(token_iter: &mut TokenIterator) -> Result<StatementKind, SyntaxError> {
+    validate_syntax!(token_iter, Token::For)?;
+    validate_syntax!(token_iter, Token::OpenParen)?;
+
+    let initializer = parse_for_clause(token_iter, &Token::Semicolon)?;
+    validate_syntax!(token_iter, Token::Semicolon)?;
+
+    let condition = match token_iter.peek() {
+        Some(Token::Semicolon) => None,
+        _ => Some(expression::parse(token_iter)?),
+    };
+    validate_syntax!(token_iter, Token::Semicolon)?;
+
+    if token_iter.peek() == Some(&Token::Let) {
+        return Err(SyntaxError::DeclarationInForStep(token_iter.peek_span()));
+    }
+    let step = parse_for_clause(token_iter, &Token::CloseParen)?;
+    validate_syntax!(token_iter, Token::CloseParen)?;
+
+    let statements = parse_block(token_iter)?;
+
+    Ok(StatementKind::For {
+        initializer,
+        condition,
+        step,
+        statements,
+    })
+}
+
+fn parse_if(token_iter: &mut TokenIterator) -> Result<StatementKind, SyntaxError> {
+    validate_syntax!(token_iter, Token::If)?;
+    validate_syntax!(token_iter, Token::OpenParen)?;
+    let condition = expression::parse(token_iter)?;
+    validate_syntax!(token_iter, Token::CloseParen)?;
+    let statements = parse_block(token_iter)?;
+
+    let mut else_statements = vec![];
+    if let Some(Token::Else) = token_iter.peek() {
+        token_iter.next();
+        match token_iter.peek() {
+            Some(Token::If) => {
+                let start = token_iter.peek_span();
+                let kind = parse_if(token_iter)?;
+                else_statements.push(Statement {
+                    kind,
+                    span: start.to(token_iter.last_span()),
+                });
+            },
+            _ => else_statements = parse_block(token_iter)?,
+        }
+    }
+
+    Ok(StatementKind::If {
+        condition,
+        statements,
+        else_statements,
+    })
+}
+
+/// Parse a `{ ... }` block of statements. A statement with a syntax error is
+/// reported and skipped so the rest of the block can still be parsed.
+pub fn parse_block(token_iter: &mut TokenIterator) -> Result<Vec<Statement>, SyntaxError> {
+    validate_syntax!(token_iter, Token::OpenBrace)?;
 
+    let mut statements = vec![];
+    loop {
+        match parse(token_iter) {
+            Ok(Some(statement)) => statements.push(statement),
+            Ok(None) => break,
+            Err(e) => {
+                token_iter.report(e);
+                token_iter.synchronize();
+            }
         }
-        _ => Ok(None)
     }
+
+    validate_syntax!(token_iter, Token::CloseBrace)?;
+    Ok(statements)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
-    use expression::Expression;
+    use expression::{ Expression, ExpressionKind };
 
     #[test]
-    fn assignment_statement() {
+    fn declaration_statement() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Let,
                 Token::Identifier("foobar".to_string()),
                 Token::Equals,
                 Token::Number(5),
                 Token::Semicolon,
-            ].iter().peekable()),
-            Ok(Some(Statement::Assignment{
+            ]))),
+            Ok(Some(Statement::unspanned(StatementKind::Declaration{
                 identifier: "foobar".to_string(),
-                value: Expression::NumberLiteral(5),
-            }))
+                value: Expression::unspanned(ExpressionKind::NumberLiteral(5)),
+            })))
         );
     }
 
     #[test]
     fn function_statement() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::Identifier("foobar".to_string()),
                 Token::OpenParen,
                 Token::CloseParen,
                 Token::Semicolon,
-            ].iter().peekable()),
-            Ok(Some(Statement::FunctionCall{
+            ]))),
+            Ok(Some(Statement::unspanned(StatementKind::FunctionCall{
                 identifier: "foobar".to_string(),
-                param: None,
-            }))
+                arguments: vec![],
+            })))
         );
     }
 
     #[test]
     fn while_statement() {
         assert_eq!(
-            parse(&mut vec![
+            parse(&mut TokenIterator::new(&unspanned(vec![
                 Token::While,
                 Token::OpenParen,
                 Token::Number(1),
@@ -135,23 +322,201 @@ mod tests {
                 Token::Semicolon,
 
                 Token::CloseBrace,
-            ].iter().peekable()),
-            Ok(Some(Statement::While{
-                condition: Expression::NotComparison {
-                    left: Box::new(Expression::NumberLiteral(1)),
-                    right: Box::new(Expression::NumberLiteral(2)),
-                },
+            ]))),
+            Ok(Some(Statement::unspanned(StatementKind::While{
+                condition: Expression::unspanned(ExpressionKind::NotComparison {
+                    left: Box::new(Expression::unspanned(ExpressionKind::NumberLiteral(1))),
+                    right: Box::new(Expression::unspanned(ExpressionKind::NumberLiteral(2))),
+                }),
                 statements: vec![
-                    Statement::Assignment {
+                    Statement::unspanned(StatementKind::Declaration {
                         identifier: "foo".to_string(),
-                        value: Expression::NumberLiteral(5),
-                    },
-                    Statement::FunctionCall {
+                        value: Expression::unspanned(ExpressionKind::NumberLiteral(5)),
+                    }),
+                    Statement::unspanned(StatementKind::FunctionCall {
                         identifier: "bar".to_string(),
-                        param: None,
-                    }
+                        arguments: vec![],
+                    })
                 ],
+            })))
+        );
+    }
+
+    fn parse_code(code: &str) -> Result<Option<Statement>, SyntaxError> {
+        let tokens = super::super::super::tokenizer::tokenize(code.to_string()).unwrap();
+        parse(&mut TokenIterator::new(&tokens))
+    }
+
+    fn strip_spans(statement: Statement) -> Statement {
+        let kind = match statement.kind {
+            StatementKind::If { condition, statements, else_statements } => StatementKind::If {
+                condition: Expression::unspanned(condition.kind),
+                statements: statements.into_iter().map(strip_spans).collect(),
+                else_statements: else_statements.into_iter().map(strip_spans).collect(),
+            },
+            StatementKind::Block { statements } => StatementKind::Block {
+                statements: statements.into_iter().map(strip_spans).collect(),
+            },
+            StatementKind::FunctionCall { identifier, arguments } => StatementKind::FunctionCall {
+                identifier,
+                arguments: arguments.into_iter().map(|a| Expression::unspanned(a.kind)).collect(),
+            },
+            kind => kind,
+        };
+        Statement::unspanned(kind)
+    }
+
+    fn call(name: &str, value: u16) -> Statement {
+        Statement::unspanned(StatementKind::FunctionCall {
+            identifier: name.to_string(),
+            arguments: vec![Expression::unspanned(ExpressionKind::NumberLiteral(value))],
+        })
+    }
+
+    #[test]
+    fn if_statements() {
+        assert_eq!(
+            parse_code("if (1) { f(1); }").map(|s| s.map(strip_spans)),
+            Ok(Some(Statement::unspanned(StatementKind::If {
+                condition: Expression::unspanned(ExpressionKind::NumberLiteral(1)),
+                statements: vec![call("f", 1)],
+                else_statements: vec![],
+            })))
+        );
+        assert_eq!(
+            parse_code("if (1) { f(1); } else if (2) { f(2); } else { f(3); f(4); }").map(|s| s.map(strip_spans)),
+            Ok(Some(Statement::unspanned(StatementKind::If {
+                condition: Expression::unspanned(ExpressionKind::NumberLiteral(1)),
+                statements: vec![call("f", 1)],
+                else_statements: vec![Statement::unspanned(StatementKind::If {
+                    condition: Expression::unspanned(ExpressionKind::NumberLiteral(2)),
+                    statements: vec![call("f", 2)],
+                    else_statements: vec![call("f", 3), call("f", 4)],
+                })],
+            })))
+        );
+    }
+
+    #[test]
+    fn else_needs_a_block_or_if() {
+        assert_eq!(
+            parse_code("if (1) { } else f(1);").map(|s| s.is_some()),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Identifier("f".to_string()),
+                expected: vec!["`{`".to_string()],
+                span: Span { start: 16, end: 17, line: 1, column: 17 },
+            })
+        );
+    }
+
+    #[test]
+    fn return_statements() {
+        assert_eq!(
+            parse_code("return;"),
+            Ok(Some(Statement {
+                kind: StatementKind::Return { value: None },
+                span: Span { start: 0, end: 7, line: 1, column: 1 },
             }))
         );
+        assert_eq!(
+            parse_code("return 5;").map(|s| s.map(|s| s.kind)),
+            Ok(Some(StatementKind::Return {
+                value: Some(Expression {
+                    kind: ExpressionKind::NumberLiteral(5),
+                    span: Span { start: 7, end: 8, line: 1, column: 8 },
+                }),
+            }))
+        );
+    }
+
+    #[test]
+    fn assignment_statements() {
+        assert_eq!(
+            parse_code("i = i + 1;").map(|s| s.map(|s| s.kind)),
+            Ok(Some(StatementKind::Assignment {
+                identifier: "i".to_string(),
+                value: Expression {
+                    kind: ExpressionKind::Addition {
+                        left: Box::new(Expression {
+                            kind: ExpressionKind::Variable("i".to_string()),
+                            span: Span { start: 4, end: 5, line: 1, column: 5 },
+                        }),
+                        right: Box::new(Expression {
+                            kind: ExpressionKind::NumberLiteral(1),
+                            span: Span { start: 8, end: 9, line: 1, column: 9 },
+                        }),
+                    },
+                    span: Span { start: 4, end: 9, line: 1, column: 5 },
+                },
+            }))
+        );
+        assert_eq!(
+            parse_code("i + 1;"),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Plus,
+                expected: vec!["`(`".to_string(), "`=`".to_string()],
+                span: Span { start: 2, end: 3, line: 1, column: 3 },
+            })
+        );
+    }
+
+    #[test]
+    fn block_statements() {
+        assert_eq!(
+            parse_code("{ f(1); { } }").map(|s| s.map(strip_spans)),
+            Ok(Some(Statement::unspanned(StatementKind::Block {
+                statements: vec![
+                    call("f", 1),
+                    Statement::unspanned(StatementKind::Block { statements: vec![] }),
+                ],
+            })))
+        );
+    }
+
+    #[test]
+    fn for_statements() {
+        let statement = parse_code("for (let i = 0; i < 3; i = i + 1) { break; continue; }").unwrap().unwrap();
+        match statement.kind {
+            StatementKind::For { initializer, condition, step, statements } => {
+                assert!(matches!(initializer.map(|s| s.kind), Some(StatementKind::Declaration { .. })));
+                assert!(matches!(condition.map(|c| c.kind), Some(ExpressionKind::LessThanComparison { .. })));
+                assert!(matches!(step.map(|s| s.kind), Some(StatementKind::Assignment { .. })));
+                assert_eq!(
+                    statements.into_iter().map(|s| s.kind).collect::<Vec<_>>(),
+                    vec![StatementKind::Break, StatementKind::Continue]
+                );
+            },
+            kind => panic!("expected a for loop, found {:?}", kind),
+        }
+
+        assert_eq!(
+            parse_code("for (;;) { }").map(|s| s.map(strip_spans)),
+            Ok(Some(Statement::unspanned(StatementKind::For {
+                initializer: None,
+                condition: None,
+                step: None,
+                statements: vec![],
+            })))
+        );
+        assert_eq!(
+            parse_code("for (print(1); ; ) { }").map(|s| s.map(|s| s.kind)).map(|kind| matches!(kind, Some(StatementKind::For { initializer: Some(_), .. }))),
+            Ok(true)
+        );
+        assert_eq!(
+            parse_code("for (i; ; ) { }"),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Semicolon,
+                expected: vec!["`(`".to_string(), "`=`".to_string()],
+                span: Span { start: 6, end: 7, line: 1, column: 7 },
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_declarations_in_for_steps() {
+        assert_eq!(
+            parse_code("for (let i = 0; i < 5; let k = i) { }"),
+            Err(SyntaxError::DeclarationInForStep(Span { start: 23, end: 26, line: 1, column: 24 }))
+        );
     }
 }
@@ -1,62 +1,107 @@
+/// Consume the next token if it's one of the given variants, otherwise
+/// leave it in place and produce a `SyntaxError` that records both what was
+/// found and what was expected. Variants are written as `Token::CloseParen`,
+/// or as `Token::Identifier(x) => x` to bind and return the value one holds.
 macro_rules! validate_syntax {
-    ( $expression:expr , $($pattern:pat)|+ ) => {
-        match $expression {
-            $($pattern)|+ => Ok(()),
-            _ => Err(SyntaxError::UnexpectedToken)
+    ( $token_iter:expr , $(Token::$variant:ident)|+ ) => {
+        match $token_iter.peek() {
+            $(Some(Token::$variant))|+ => {
+                $token_iter.next();
+                Ok(())
+            },
+            found => Err(SyntaxError::unexpected(
+                found,
+                vec![$($crate::tokenizer::TokenKind::$variant.describe().to_string()),+],
+                $token_iter.peek_span(),
+            ))
         }
     };
 
-    ( $expression:expr , $pat:pat => $arm:expr ) => {
-        match $expression {
-            $pat => Ok($arm),
-            _ => Err(SyntaxError::UnexpectedToken)
+    ( $token_iter:expr , Token::$variant:ident($value:pat) => $arm:expr ) => {
+        match $token_iter.peek() {
+            Some(Token::$variant($value)) => {
+                $token_iter.next();
+                Ok($arm)
+            },
+            found => Err(SyntaxError::unexpected(
+                found,
+                vec![$crate::tokenizer::TokenKind::$variant.describe().to_string()],
+                $token_iter.peek_span(),
+            ))
         }
     };
 }
 
 #[cfg(test)]
 mod tests {
-    use super::super::SyntaxError;
-
-    #[allow(dead_code)]
-    enum ForTesting {
-        Foo,
-        Bar,
-        None,
-    }
+    use super::super::{ unspanned, SyntaxError, TokenIterator };
+    use super::super::super::span::Span;
+    use super::super::super::tokenizer::Token;
 
     #[test]
     fn macro_passes_if_there_is_a_match() {
-        let x = vec![5];
+        let tokens = unspanned(vec![Token::Semicolon]);
+        let mut token_iter = TokenIterator::new(&tokens);
         assert_eq!(
-            validate_syntax!(x.get(0), Some(5)),
+            validate_syntax!(token_iter, Token::Semicolon),
             Ok(())
         );
     }
 
     #[test]
     fn macro_passes_with_multiple_match() {
-        let x = ForTesting::Bar;
+        let tokens = unspanned(vec![Token::CloseParen]);
+        let mut token_iter = TokenIterator::new(&tokens);
         assert_eq!(
-            validate_syntax!(x, ForTesting::Foo | ForTesting::Bar),
+            validate_syntax!(token_iter, Token::Semicolon | Token::CloseParen),
             Ok(())
         );
     }
 
     #[test]
     fn macro_fails_if_there_is_not_a_match() {
-        let x = vec![];
+        let tokens = unspanned(vec![]);
+        let mut token_iter = TokenIterator::new(&tokens);
         assert_eq!(
-            validate_syntax!(x.get(0), Some(5)),
-            Err(SyntaxError::UnexpectedToken)
-        )
+            validate_syntax!(token_iter, Token::Semicolon),
+            Err(SyntaxError::UnexpectedEndOfInput {
+                expected: vec!["`;`".to_string()],
+                span: Span { start: 0, end: 0, line: 1, column: 1 },
+            })
+        );
+
+        let tokens = unspanned(vec![Token::Equals]);
+        let mut token_iter = TokenIterator::new(&tokens);
+        assert_eq!(
+            validate_syntax!(token_iter, Token::Semicolon | Token::CloseParen),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Equals,
+                expected: vec!["`;`".to_string(), "`)`".to_string()],
+                span: Span::default(),
+            })
+        );
+    }
+
+    #[test]
+    fn macro_fails_without_a_value_to_bind() {
+        let tokens = unspanned(vec![Token::Semicolon]);
+        let mut token_iter = TokenIterator::new(&tokens);
+        assert_eq!(
+            validate_syntax!(token_iter, Token::Identifier(x) => x.clone()),
+            Err(SyntaxError::UnexpectedToken {
+                found: Token::Semicolon,
+                expected: vec!["identifier".to_string()],
+                span: Span::default(),
+            })
+        );
     }
 
     #[test]
     fn macro_runs_and_returns_expr() {
-        let x = vec![5];
+        let tokens = unspanned(vec![Token::Number(5)]);
+        let mut token_iter = TokenIterator::new(&tokens);
         assert_eq!(
-            validate_syntax!(x.get(0), Some(y) => *y),
+            validate_syntax!(token_iter, Token::Number(y) => *y),
             Ok(5)
         )
     }
@@ -0,0 +1,536 @@
+use std::collections::HashMap;
+use super::diagnostics::Diagnostic;
+use super::gen::BUILTIN_FUNCTIONS;
+use super::parser::Program;
+use super::parser::function::Function;
+use super::parser::statement::{ Statement, StatementKind };
+use super::parser::expression::{ Expression, ExpressionKind };
+use super::span::Span;
+
+pub mod types;
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum SemanticError {
+    UndefinedVariable(String, Span),
+    UndefinedFunction(String, Span),
+    ArityMismatch {
+        name: String,
+        expected: usize,
+        found: usize,
+        span: Span,
+    },
+    DuplicateFunction {
+        name: String,
+        span: Span,
+        /// Where the function was first defined, or `None` for builtins
+        previous: Option<Span>,
+    },
+    MissingMain,
+    MainWithParameters(Span),
+    ReturnOutsideFunction(Span),
+    BreakOutsideLoop(Span),
+    ContinueOutsideLoop(Span),
+    Redeclaration {
+        name: String,
+        span: Span,
+        previous: Span,
+    },
+}
+
+impl SemanticError {
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        match self {
+            SemanticError::UndefinedVariable(name, span) => Diagnostic::error(format!("cannot find variable `{}`", name))
+                .with_span(*span)
+                .with_label("not found in this scope"),
+            SemanticError::UndefinedFunction(name, span) => Diagnostic::error(format!("cannot find function `{}`", name))
+                .with_span(*span)
+                .with_label("not found in this program"),
+            SemanticError::ArityMismatch { name, expected, found, span } => Diagnostic::error(format!(
+                    "function `{}` takes {} argument{} but {} {} supplied",
+                    name, expected, plural(*expected), found, if *found == 1 { "was" } else { "were" }
+                ))
+                .with_span(*span)
+                .with_label(format!("expected {} argument{}", expected, plural(*expected))),
+            SemanticError::DuplicateFunction { name, span, previous } => {
+                let note = match previous {
+                    Some(previous) => format!("`{}` was first defined at {}", name, previous),
+                    None => format!("`{}` is a built-in function", name),
+                };
+                Diagnostic::error(format!("function `{}` is defined more than once", name))
+                    .with_span(*span)
+                    .with_label("redefined here")
+                    .with_note(note)
+            },
+            SemanticError::MissingMain => Diagnostic::error("no `main` function found")
+                .with_note("execution starts by calling `fn main()`"),
+            SemanticError::MainWithParameters(span) => Diagnostic::error("`main` can't take parameters")
+                .with_span(*span)
+                .with_label("declared with parameters here")
+                .with_note("execution starts by calling `fn main()` with no arguments"),
+            SemanticError::ReturnOutsideFunction(span) => Diagnostic::error("`return` outside of a function")
+                .with_span(*span)
+                .with_label("top level statements can't return"),
+            SemanticError::BreakOutsideLoop(span) => Diagnostic::error("`break` outside of a loop")
+                .with_span(*span)
+                .with_label("cannot `break` outside of a loop"),
+            SemanticError::ContinueOutsideLoop(span) => Diagnostic::error("`continue` outside of a loop")
+                .with_span(*span)
+                .with_label("cannot `continue` outside of a loop"),
+            SemanticError::Redeclaration { name, span, previous } => Diagnostic::error(format!("`{}` is already declared in this scope", name))
+                .with_span(*span)
+                .with_label("redeclared here")
+                .with_note(format!("`{}` was first declared at {}", name, previous))
+                .with_note("declaring it again inside a nested block would shadow it instead"),
+        }
+    }
+}
+
+fn plural(count: usize) -> &'static str {
+    if count == 1 { "" } else { "s" }
+}
+
+struct FunctionSymbol {
+    arity: usize,
+    /// `None` for builtins
+    span: Option<Span>,
+}
+
+/// The names visible while walking the program, along with where each
+/// variable was declared
+struct SymbolTable {
+    functions: HashMap<String, FunctionSymbol>,
+    globals: HashMap<String, Span>,
+    /// Nested block scopes, innermost last. Empty at the top level, where
+    /// declarations make globals.
+    scopes: Vec<HashMap<String, Span>>,
+    in_function: bool,
+    /// How many loops enclose the statement being checked
+    loop_depth: usize,
+}
+
+impl SymbolTable {
+    fn is_variable(&self, name: &str) -> bool {
+        self.scopes.iter().any(|scope| scope.contains_key(name)) || self.globals.contains_key(name)
+    }
+
+    /// Declare `name` in the innermost scope, failing with the previous
+    /// declaration's span if that scope already has one
+    fn declare(&mut self, name: &str, span: Span) -> Result<(), Span> {
+        let scope = self.scopes.last_mut().unwrap_or(&mut self.globals);
+        match scope.get(name) {
+            Some(previous) => Err(*previous),
+            None => {
+                scope.insert(name.to_string(), span);
+                Ok(())
+            },
+        }
+    }
+}
+
+/// Check that `program` makes sense beyond being syntactically valid: every
+/// name it uses is defined, calls pass the right number of arguments, and
+/// there's exactly one of each function including a parameterless `main`.
+pub fn analyze(program: &Program) -> Vec<SemanticError> {
+    let mut errors = vec![];
+    let mut symbols = SymbolTable {
+        functions: HashMap::new(),
+        globals: HashMap::new(),
+        scopes: vec![],
+        in_function: false,
+        loop_depth: 0,
+    };
+
+    for (name, arity) in BUILTIN_FUNCTIONS {
+        symbols.functions.insert(name.to_string(), FunctionSymbol { arity: *arity, span: None });
+    }
+
+    for function in &program.functions {
+        if let Some(previous) = symbols.functions.get(&function.identifier) {
+            errors.push(SemanticError::DuplicateFunction {
+                name: function.identifier.clone(),
+                span: function.span,
+                previous: previous.span,
+            });
+            continue;
+        }
+
+        symbols.functions.insert(function.identifier.clone(), FunctionSymbol {
+            arity: function.parameters.len(),
+            span: Some(function.span),
+        });
+    }
+
+    match program.functions.iter().find(|f| f.identifier == "main") {
+        Some(main) if !main.parameters.is_empty() => errors.push(SemanticError::MainWithParameters(main.span)),
+        Some(_) => (),
+        None => errors.push(SemanticError::MissingMain),
+    }
+
+    // Globals are defined in order, before any function runs
+    for statement in &program.statements {
+        check_statement(&mut symbols, statement, &mut errors);
+    }
+
+    symbols.in_function = true;
+    for function in &program.functions {
+        check_function(&mut symbols, function, &mut errors);
+    }
+
+    errors
+}
+
+fn check_function(symbols: &mut SymbolTable, function: &Function, errors: &mut Vec<SemanticError>) {
+    // Parameters share a scope with the outermost block of the body
+    symbols.scopes = vec![HashMap::new()];
+    for parameter in &function.parameters {
+        if let Err(previous) = symbols.declare(&parameter.node, parameter.span) {
+            errors.push(SemanticError::Redeclaration {
+                name: parameter.node.clone(),
+                span: parameter.span,
+                previous,
+            });
+        }
+    }
+
+    for statement in &function.statements {
+        check_statement(symbols, statement, errors);
+    }
+
+    symbols.scopes.clear();
+}
+
+fn check_block(symbols: &mut SymbolTable, statements: &[Statement], errors: &mut Vec<SemanticError>) {
+    symbols.scopes.push(HashMap::new());
+    for statement in statements {
+        check_statement(symbols, statement, errors);
+    }
+    symbols.scopes.pop();
+}
+
+fn check_statement(symbols: &mut SymbolTable, statement: &Statement, errors: &mut Vec<SemanticError>) {
+    match &statement.kind {
+        StatementKind::Declaration { identifier, value } => {
+            // The value is checked first, so `let x = x;` refers to any outer `x`
+            check_expression(symbols, value, errors);
+            if let Err(previous) = symbols.declare(identifier, statement.span) {
+                errors.push(SemanticError::Redeclaration {
+                    name: identifier.clone(),
+                    span: statement.span,
+                    previous,
+                });
+            }
+        },
+        StatementKind::Assignment { identifier, value } => {
+            check_expression(symbols, value, errors);
+            if !symbols.is_variable(identifier) {
+                errors.push(SemanticError::UndefinedVariable(identifier.clone(), statement.span));
+            }
+        },
+        StatementKind::Block { statements } => check_block(symbols, statements, errors),
+        StatementKind::FunctionCall { identifier, arguments } => {
+            check_call(symbols, identifier, arguments, statement.span, errors);
+        },
+        StatementKind::Return { value } => {
+            if let Some(value) = value {
+                check_expression(symbols, value, errors);
+            }
+            if !symbols.in_function {
+                errors.push(SemanticError::ReturnOutsideFunction(statement.span));
+            }
+        },
+        StatementKind::While { condition, statements } => {
+            check_expression(symbols, condition, errors);
+            symbols.loop_depth += 1;
+            check_block(symbols, statements, errors);
+            symbols.loop_depth -= 1;
+        },
+        StatementKind::For { initializer, condition, step, statements } => {
+            // The initializer's declarations are scoped to the loop
+            symbols.scopes.push(HashMap::new());
+            if let Some(initializer) = initializer {
+                check_statement(symbols, initializer, errors);
+            }
+            if let Some(condition) = condition {
+                check_expression(symbols, condition, errors);
+            }
+            symbols.loop_depth += 1;
+            check_block(symbols, statements, errors);
+            symbols.loop_depth -= 1;
+            if let Some(step) = step {
+                check_statement(symbols, step, errors);
+            }
+            symbols.scopes.pop();
+        },
+        StatementKind::Break if symbols.loop_depth == 0 => {
+            errors.push(SemanticError::BreakOutsideLoop(statement.span));
+        },
+        StatementKind::Continue if symbols.loop_depth == 0 => {
+            errors.push(SemanticError::ContinueOutsideLoop(statement.span));
+        },
+        StatementKind::Break | StatementKind::Continue => (),
+        StatementKind::If { condition, statements, else_statements } => {
+            check_expression(symbols, condition, errors);
+            check_block(symbols, statements, errors);
+            check_block(symbols, else_statements, errors);
+        },
+    }
+}
+
+fn check_call(symbols: &SymbolTable, identifier: &str, arguments: &[Expression], span: Span, errors: &mut Vec<SemanticError>) {
+    for argument in arguments {
+        check_expression(symbols, argument, errors);
+    }
+
+    let found = arguments.len();
+    match symbols.functions.get(identifier) {
+        Some(function) if function.arity != found => errors.push(SemanticError::ArityMismatch {
+            name: identifier.to_string(),
+            expected: function.arity,
+            found,
+            span,
+        }),
+        Some(_) => (),
+        None => errors.push(SemanticError::UndefinedFunction(identifier.to_string(), span)),
+    }
+}
+
+fn check_expression(symbols: &SymbolTable, expression: &Expression, errors: &mut Vec<SemanticError>) {
+    match &expression.kind {
+        ExpressionKind::NumberLiteral(_) | ExpressionKind::StringLiteral(_) => (),
+        ExpressionKind::Variable(name) => {
+            if !symbols.is_variable(name) {
+                errors.push(SemanticError::UndefinedVariable(name.clone(), expression.span));
+            }
+        },
+        ExpressionKind::FunctionCall { identifier, arguments } => {
+            check_call(symbols, identifier, arguments, expression.span, errors);
+        },
+        ExpressionKind::Lookup { base: left, index: right }
+        | ExpressionKind::NotComparison { left, right }
+        | ExpressionKind::EqualComparison { left, right }
+        | ExpressionKind::LessThanComparison { left, right }
+        | ExpressionKind::LessThanOrEqualComparison { left, right }
+        | ExpressionKind::GreaterThanComparison { left, right }
+        | ExpressionKind::GreaterThanOrEqualComparison { left, right }
+        | ExpressionKind::Addition { left, right }
+        | ExpressionKind::Subtraction { left, right }
+        | ExpressionKind::Multiplication { left, right }
+        | ExpressionKind::Division { left, right }
+        | ExpressionKind::Remainder { left, right }
+        | ExpressionKind::LogicalAnd { left, right }
+        | ExpressionKind::LogicalOr { left, right }
+        | ExpressionKind::BitwiseAnd { left, right }
+        | ExpressionKind::BitwiseOr { left, right }
+        | ExpressionKind::BitwiseXor { left, right }
+        | ExpressionKind::ShiftLeft { left, right }
+        | ExpressionKind::ShiftRight { left, right } => {
+            check_expression(symbols, left, errors);
+            check_expression(symbols, right, errors);
+        },
+        ExpressionKind::Negation { operand }
+        | ExpressionKind::LogicalNot { operand }
+        | ExpressionKind::BitwiseNot { operand } => check_expression(symbols, operand, errors),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::{ parser, tokenizer };
+
+    fn analyze_code(code: &str) -> Vec<SemanticError> {
+        let (program, errors) = parser::parse(tokenizer::tokenize(code.to_string()).unwrap());
+        assert_eq!(errors, vec![]);
+        analyze(&program)
+    }
+
+    fn messages(code: &str) -> Vec<String> {
+        analyze_code(code).iter().map(|e| e.to_diagnostic().message).collect::<Vec<_>>()
+    }
+
+    #[test]
+    fn accepts_valid_programs() {
+        let code = r#"
+            let greeting = "Hi";
+
+            fn print_string(string) {
+                let i = 0;
+                while (string[i] != 0) {
+                    print(string[i]);
+                    i = i + 1;
+                }
+            }
+
+            fn main() {
+                print_string(greeting);
+            }
+        "#;
+
+        assert_eq!(analyze_code(code), vec![]);
+    }
+
+    #[test]
+    fn reports_undefined_names() {
+        assert_eq!(
+            messages("fn main() { print(x); missing(); }"),
+            vec!["cannot find variable `x`", "cannot find function `missing`"]
+        );
+    }
+
+    #[test]
+    fn locals_are_not_visible_from_other_functions() {
+        assert_eq!(
+            messages("fn other() { let x = 1; } fn main() { print(x); }"),
+            vec!["cannot find variable `x`"]
+        );
+    }
+
+    #[test]
+    fn variables_must_be_defined_before_use() {
+        assert_eq!(
+            messages("fn main() { print(x); let x = 1; }"),
+            vec!["cannot find variable `x`"]
+        );
+    }
+
+    #[test]
+    fn reports_arity_mismatches() {
+        assert_eq!(
+            messages("fn takes_one(a) { } fn takes_two(a, b) { } fn main() { takes_one(); print(); main(1); takes_two(1, 2, 3); takes_two(1, 2); }"),
+            vec![
+                "function `takes_one` takes 1 argument but 0 were supplied",
+                "function `print` takes 1 argument but 0 were supplied",
+                "function `main` takes 0 arguments but 1 was supplied",
+                "function `takes_two` takes 2 arguments but 3 were supplied",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_duplicate_functions() {
+        let errors = analyze_code("fn main() { }\nfn main() { }\nfn print(c) { }");
+
+        assert_eq!(errors.len(), 2);
+        assert!(matches!(&errors[0], SemanticError::DuplicateFunction { previous: Some(span), .. } if span.line == 1));
+        assert!(matches!(&errors[1], SemanticError::DuplicateFunction { previous: None, .. }));
+    }
+
+    #[test]
+    fn checks_calls_inside_expressions() {
+        assert_eq!(
+            messages("fn one() { return 1; } fn main() { let x = one(2) + two(); }"),
+            vec!["function `one` takes 0 arguments but 1 was supplied", "cannot find function `two`"]
+        );
+    }
+
+    #[test]
+    fn reports_return_outside_functions() {
+        assert_eq!(
+            messages("return 1;\nfn main() { return; }"),
+            vec!["`return` outside of a function"]
+        );
+    }
+
+    #[test]
+    fn reports_redeclarations_in_the_same_scope() {
+        assert_eq!(
+            messages(r#"
+                let g = 1;
+                let g = 2;
+
+                fn f(a) {
+                    let a = 1;
+                    let b = 2;
+                    {
+                        let a = 3;
+                        let b = 4;
+                        let b = 5;
+                    }
+                    while (1) {
+                        let b = 6;
+                    }
+                    let g = 7;
+                }
+
+                fn main() { }
+            "#),
+            vec![
+                "`g` is already declared in this scope",
+                "`a` is already declared in this scope",
+                "`b` is already declared in this scope",
+            ]
+        );
+    }
+
+    #[test]
+    fn reports_repeated_parameters() {
+        assert_eq!(
+            analyze_code("fn f(a, b, a) { }\nfn main() { }"),
+            vec![SemanticError::Redeclaration {
+                name: "a".to_string(),
+                span: Span { start: 11, end: 12, line: 1, column: 12 },
+                previous: Span { start: 5, end: 6, line: 1, column: 6 },
+            }]
+        );
+    }
+
+    #[test]
+    fn block_scoped_variables_are_not_visible_after_the_block() {
+        assert_eq!(
+            messages("fn main() { if (1) { let x = 1; } x = 2; { let y = 1; } print(y); }"),
+            vec!["cannot find variable `x`", "cannot find variable `y`"]
+        );
+    }
+
+    #[test]
+    fn assignments_need_a_declared_variable() {
+        assert_eq!(
+            messages("let a = 1; fn main() { a = 2; b = 3; }"),
+            vec!["cannot find variable `b`"]
+        );
+    }
+
+    #[test]
+    fn reports_break_and_continue_outside_loops() {
+        assert_eq!(
+            messages(r#"
+                break;
+
+                fn main() {
+                    while (1) {
+                        if (1) { break; }
+                        { continue; }
+                    }
+                    for (let i = 0; i < 10; i = i + 1) {
+                        continue;
+                    }
+                    continue;
+                    if (1) { break; }
+                }
+            "#),
+            vec!["`break` outside of a loop", "`continue` outside of a loop", "`break` outside of a loop"]
+        );
+    }
+
+    #[test]
+    fn for_loop_variables_are_scoped_to_the_loop() {
+        assert_eq!(
+            messages("fn main() { for (let i = 0; i < 3; i = i + 1) { let i = i; } for (let i = 0; ; ) { } print(i); }"),
+            vec!["cannot find variable `i`"]
+        );
+    }
+
+    #[test]
+    fn reports_missing_main() {
+        assert_eq!(analyze_code("fn start() { }"), vec![SemanticError::MissingMain]);
+    }
+
+    #[test]
+    fn reports_main_with_parameters() {
+        assert_eq!(
+            analyze_code("fn main(x) { print(x); }"),
+            vec![SemanticError::MainWithParameters(Span { start: 0, end: 24, line: 1, column: 1 })]
+        );
+    }
+}
@@ -0,0 +1,118 @@
+//! Whether values are signed. The language has no type annotations, so the
+//! type of an expression is inferred from its operands the way C's usual
+//! arithmetic conversions would, with `unsigned` winning over `int`.
+//!
+//! Variables keep the type they're declared with and parameters are always
+//! signed, since there's no syntax to say otherwise. The README spells these
+//! rules out for users.
+
+use super::super::parser::expression::{ Expression, ExpressionKind };
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Type {
+    /// Two's complement, from -32768 to 32767
+    Signed,
+    /// From 0 to 65535, such as addresses like `0xB800`
+    Unsigned,
+}
+
+impl Type {
+    /// The type both operands of a binary operation are converted to
+    pub fn common(self, other: Type) -> Type {
+        if self == Type::Unsigned || other == Type::Unsigned {
+            Type::Unsigned
+        } else {
+            Type::Signed
+        }
+    }
+}
+
+/// The type of `expression`, given the type of each variable it uses.
+///
+/// - Literals are signed unless they only fit as unsigned, like `40000`.
+///   Negated literals are signed down to `-32768`.
+/// - Strings are addresses, so unsigned
+/// - Arithmetic and bitwise operators are unsigned if either operand is,
+///   while shifts and `-`/`~` keep the type of the value being operated on
+/// - Comparisons, logical operators, lookups (single bytes) and calls
+///   (whose return types aren't tracked) are signed
+pub fn type_of(expression: &Expression, variable: &impl Fn(&str) -> Type) -> Type {
+    match &expression.kind {
+        ExpressionKind::NumberLiteral(value) if *value > i16::MAX as u16 => Type::Unsigned,
+        ExpressionKind::NumberLiteral(_) => Type::Signed,
+        ExpressionKind::StringLiteral(_) => Type::Unsigned,
+        ExpressionKind::Variable(name) => variable(name),
+        ExpressionKind::Addition { left, right }
+        | ExpressionKind::Subtraction { left, right }
+        | ExpressionKind::Multiplication { left, right }
+        | ExpressionKind::Division { left, right }
+        | ExpressionKind::Remainder { left, right }
+        | ExpressionKind::BitwiseAnd { left, right }
+        | ExpressionKind::BitwiseOr { left, right }
+        | ExpressionKind::BitwiseXor { left, right } => type_of(left, variable).common(type_of(right, variable)),
+        ExpressionKind::ShiftLeft { left, .. } | ExpressionKind::ShiftRight { left, .. } => type_of(left, variable),
+        ExpressionKind::Negation { operand }
+            if matches!(operand.kind, ExpressionKind::NumberLiteral(value) if value <= 32768) => Type::Signed,
+        ExpressionKind::Negation { operand } | ExpressionKind::BitwiseNot { operand } => type_of(operand, variable),
+        ExpressionKind::FunctionCall { .. }
+        | ExpressionKind::Lookup { .. }
+        | ExpressionKind::NotComparison { .. }
+        | ExpressionKind::EqualComparison { .. }
+        | ExpressionKind::LessThanComparison { .. }
+        | ExpressionKind::LessThanOrEqualComparison { .. }
+        | ExpressionKind::GreaterThanComparison { .. }
+        | ExpressionKind::GreaterThanOrEqualComparison { .. }
+        | ExpressionKind::LogicalAnd { .. }
+        | ExpressionKind::LogicalOr { .. }
+        | ExpressionKind::LogicalNot { .. } => Type::Signed,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use super::super::super::{ parser, tokenizer };
+
+    /// The type of `expression` where `segment` is unsigned and anything
+    /// else signed
+    fn type_of_code(expression: &str) -> Type {
+        let code = format!("let result = {};", expression);
+        let (program, errors) = parser::parse(tokenizer::tokenize(code).unwrap());
+        assert_eq!(errors, vec![]);
+
+        let value = match &program.statements[0].kind {
+            parser::statement::StatementKind::Declaration { value, .. } => value,
+            kind => panic!("expected a declaration, found {:?}", kind),
+        };
+        type_of(value, &|name| if name == "segment" { Type::Unsigned } else { Type::Signed })
+    }
+
+    #[test]
+    fn literals_are_unsigned_only_when_they_need_to_be() {
+        assert_eq!(type_of_code("32767"), Type::Signed);
+        assert_eq!(type_of_code("0x7c00"), Type::Signed);
+        assert_eq!(type_of_code("32768"), Type::Unsigned);
+        assert_eq!(type_of_code("0xB800"), Type::Unsigned);
+        assert_eq!(type_of_code("\"text\""), Type::Unsigned);
+        assert_eq!(type_of_code("-32768"), Type::Signed);
+        assert_eq!(type_of_code("-32769"), Type::Unsigned);
+    }
+
+    #[test]
+    fn unsigned_operands_win() {
+        assert_eq!(type_of_code("1 + 2"), Type::Signed);
+        assert_eq!(type_of_code("1 + 0xB800"), Type::Unsigned);
+        assert_eq!(type_of_code("x / segment"), Type::Unsigned);
+        assert_eq!(type_of_code("-segment"), Type::Unsigned);
+        assert_eq!(type_of_code("segment >> x"), Type::Unsigned);
+        assert_eq!(type_of_code("x >> segment"), Type::Signed);
+    }
+
+    #[test]
+    fn truth_values_bytes_and_calls_are_signed() {
+        assert_eq!(type_of_code("segment < 0xB800"), Type::Signed);
+        assert_eq!(type_of_code("segment && 0xB800"), Type::Signed);
+        assert_eq!(type_of_code("\"text\"[segment]"), Type::Signed);
+        assert_eq!(type_of_code("f(segment)"), Type::Signed);
+    }
+}
@@ -0,0 +1,51 @@
+use std::fmt;
+
+/// A region of the source text. `start` and `end` are byte offsets into the
+/// source while `line` and `column` (both 1-based) locate `start` for humans.
+#[derive(Debug, PartialEq, Eq, Clone, Copy, Default)]
+pub struct Span {
+    pub start: usize,
+    pub end: usize,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl Span {
+    /// Produce a span that covers both `self` and `other`
+    pub fn to(self, other: Span) -> Span {
+        let (first, last) = if self.start <= other.start { (self, other) } else { (other, self) };
+        Span {
+            start: first.start,
+            end: first.end.max(last.end),
+            line: first.line,
+            column: first.column,
+        }
+    }
+}
+
+impl fmt::Display for Span {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}", self.line, self.column)
+    }
+}
+
+/// A value tagged with the region of source it came from
+#[derive(Debug, PartialEq, Eq, Clone)]
+pub struct Spanned<T> {
+    pub node: T,
+    pub span: Span,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn joins_spans() {
+        let a = Span { start: 4, end: 6, line: 1, column: 5 };
+        let b = Span { start: 10, end: 15, line: 2, column: 3 };
+
+        assert_eq!(a.to(b), Span { start: 4, end: 15, line: 1, column: 5 });
+        assert_eq!(b.to(a), Span { start: 4, end: 15, line: 1, column: 5 });
+    }
+}
@@ -24,24 +24,109 @@ fn get_word(char_iter: &mut CharIterator) -> String {
     word
 }
 
-fn parse_number(word: String) -> Result<Token, TokenizationError> {
-    if let Ok(number) = word.parse::<i16>() {
-        Ok(Token::Number(number))
-    } else {
-        Err(TokenizationError::UnexpectedCharacter)
+/// Parse a decimal number or one prefixed with `0x`, `0b` or `0o` for hex,
+/// binary or octal. Any of them may be broken up with `_` separators.
+fn parse_number(word: String, span: Span) -> Result<Token, TokenizationError> {
+    let lowercase = word.to_ascii_lowercase();
+    let (digits, radix) = match lowercase.get(..2) {
+        Some("0x") => (&lowercase[2..], 16),
+        Some("0b") => (&lowercase[2..], 2),
+        Some("0o") => (&lowercase[2..], 8),
+        _ => (&lowercase[..], 10),
+    };
+    let digits: String = digits.chars().filter(|&c| c != '_').collect();
+
+    // from_str_radix would also accept a sign, which can't be part of a word
+    if digits.is_empty() || !digits.chars().all(|c| c.is_digit(radix)) {
+        return Err(TokenizationError::UnexpectedCharacter(span));
+    }
+
+    // With the digits checked, too many of them is the only way to fail
+    match u16::from_str_radix(&digits, radix) {
+        Ok(number) => Ok(Token::Number(number)),
+        Err(_) => Err(TokenizationError::IntegerLiteralOutOfRange(word, span)),
     }
 }
 
 pub fn parse(char_iter: &mut CharIterator) -> Result<Token, TokenizationError> {
+    let start = char_iter.location();
     let word = get_word(char_iter);
+    let span = char_iter.span_from(start);
     let first_char = word.chars().next().expect("alphanumeric::parse called at an invalid cursor position");
 
     match &word[..] {
         "while" => Ok(Token::While),
+        "for" => Ok(Token::For),
+        "break" => Ok(Token::Break),
+        "continue" => Ok(Token::Continue),
+        "if" => Ok(Token::If),
+        "else" => Ok(Token::Else),
         "let" => Ok(Token::Let),
         "fn" => Ok(Token::Function),
-        _ if first_char.is_numeric() => parse_number(word),
+        "return" => Ok(Token::Return),
+        _ if first_char.is_numeric() => parse_number(word, span),
         _ if is_alphabetic(first_char) => Ok(Token::Identifier(word)),
-        _ => Err(TokenizationError::UnexpectedCharacter)
+        _ => Err(TokenizationError::UnexpectedCharacter(span))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_code(code: &str) -> Result<Token, TokenizationError> {
+        parse(&mut CharIterator::new(code))
+    }
+
+    #[test]
+    fn parses_number_bases() {
+        assert_eq!(parse_code("1234"), Ok(Token::Number(1234)));
+        assert_eq!(parse_code("0x7c00"), Ok(Token::Number(0x7c00)));
+        assert_eq!(parse_code("0X0E"), Ok(Token::Number(14)));
+        assert_eq!(parse_code("0b01010101"), Ok(Token::Number(0b01010101)));
+        assert_eq!(parse_code("0o755"), Ok(Token::Number(0o755)));
+        assert_eq!(parse_code("007"), Ok(Token::Number(7)));
+    }
+
+    #[test]
+    fn allows_separators() {
+        assert_eq!(parse_code("10_000"), Ok(Token::Number(10000)));
+        assert_eq!(parse_code("0b0101_0101"), Ok(Token::Number(0b0101_0101)));
+        assert_eq!(parse_code("0x_7c_00_"), Ok(Token::Number(0x7c00)));
+    }
+
+    #[test]
+    fn rejects_malformed_numbers() {
+        for code in ["0x", "0b_", "0b102", "0o8", "0xfg", "12ab", "1_2x"] {
+            assert_eq!(
+                parse_code(code),
+                Err(TokenizationError::UnexpectedCharacter(Span { start: 0, end: code.len(), line: 1, column: 1 })),
+                "{}", code
+            );
+        }
+    }
+
+    #[test]
+    fn parses_unsigned_16_bit_literals() {
+        assert_eq!(parse_code("40000"), Ok(Token::Number(40000)));
+        assert_eq!(parse_code("0xB800"), Ok(Token::Number(0xb800)));
+        assert_eq!(parse_code("65535"), Ok(Token::Number(u16::MAX)));
+        assert_eq!(parse_code("0b1111_1111_1111_1111"), Ok(Token::Number(u16::MAX)));
+    }
+
+    #[test]
+    fn reports_out_of_range_literals() {
+        for code in ["65536", "0x1_0000", "0o200000", "99999999999999999999"] {
+            assert_eq!(
+                parse_code(code),
+                Err(TokenizationError::IntegerLiteralOutOfRange(code.to_string(), Span { start: 0, end: code.len(), line: 1, column: 1 })),
+            );
+        }
+    }
+
+    #[test]
+    fn parses_keywords_and_identifiers() {
+        assert_eq!(parse_code("while"), Ok(Token::While));
+        assert_eq!(parse_code("_while2"), Ok(Token::Identifier("_while2".to_string())));
     }
 }
@@ -0,0 +1,143 @@
+use super::*;
+
+/// Read the byte an escape inside a character or string literal stands for,
+/// starting with the backslash itself
+///
+/// | Escape   | Value                        |
+/// |----------|------------------------------|
+/// | `\n`     | line feed                    |
+/// | `\r`     | carriage return              |
+/// | `\t`     | tab                          |
+/// | `\0`     | zero                         |
+/// | `\\`     | backslash                    |
+/// | `\'`     | single quote                 |
+/// | `\"`     | double quote                 |
+/// | `\xHH`   | the byte with hex value `HH` |
+pub fn parse_escape(char_iter: &mut CharIterator) -> Result<u8, TokenizationError> {
+    let start = char_iter.location();
+    assert_eq!(char_iter.next(), Some('\\'));
+
+    let byte = match char_iter.next() {
+        Some('n') => b'\n',
+        Some('r') => b'\r',
+        Some('t') => b'\t',
+        Some('0') => 0,
+        Some('\\') => b'\\',
+        Some('\'') => b'\'',
+        Some('"') => b'"',
+        Some('x') => {
+            let mut value = 0;
+            for _ in 0..2 {
+                match char_iter.peek().and_then(|c| c.to_digit(16)) {
+                    Some(digit) => {
+                        char_iter.next();
+                        value = value * 16 + digit as u8;
+                    },
+                    None => return Err(TokenizationError::InvalidEscapeSequence(char_iter.span_from(start))),
+                }
+            }
+            value
+        },
+        _ => return Err(TokenizationError::InvalidEscapeSequence(char_iter.span_from(start))),
+    };
+
+    Ok(byte)
+}
+
+/// Read one character of a literal as the byte that displays it, either
+/// from an escape or by encoding it to code page 437
+pub fn parse_byte(char_iter: &mut CharIterator) -> Result<Option<u8>, TokenizationError> {
+    let start = char_iter.location();
+
+    match char_iter.peek() {
+        Some('\\') => parse_escape(char_iter).map(Some),
+        Some(&c) => {
+            char_iter.next();
+            match cp437::encode(c) {
+                Some(byte) => Ok(Some(byte)),
+                None => Err(TokenizationError::UnencodableCharacter(c, char_iter.span_from(start))),
+            }
+        },
+        None => Ok(None),
+    }
+}
+
+/// Parse a literal such as `'A'` or `'\n'` into the number of its character
+pub fn parse(char_iter: &mut CharIterator) -> Result<Token, TokenizationError> {
+    let start = char_iter.location();
+
+    // Skip the opening quote
+    assert_eq!(char_iter.next(), Some('\''));
+
+    let byte = match char_iter.peek() {
+        Some('\'' | '\n') | None => None,
+        Some(_) => parse_byte(char_iter)?,
+    };
+
+    match byte {
+        Some(byte) if char_iter.peek() == Some(&'\'') => {
+            char_iter.next();
+            Ok(Token::Number(byte as u16))
+        },
+        _ => Err(TokenizationError::InvalidCharacterLiteral(char_iter.span_from(start))),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_code(code: &str) -> Result<Token, TokenizationError> {
+        parse(&mut CharIterator::new(code))
+    }
+
+    #[test]
+    fn extracts_character_literals() {
+        assert_eq!(parse_code("'A'"), Ok(Token::Number(65)));
+        assert_eq!(parse_code("' '"), Ok(Token::Number(32)));
+        assert_eq!(parse_code("'\"'"), Ok(Token::Number(34)));
+        assert_eq!(parse_code("'é'"), Ok(Token::Number(0x82)));
+        assert_eq!(parse_code("'█'"), Ok(Token::Number(0xdb)));
+    }
+
+    #[test]
+    fn extracts_escapes() {
+        assert_eq!(parse_code(r"'\n'"), Ok(Token::Number(10)));
+        assert_eq!(parse_code(r"'\r'"), Ok(Token::Number(13)));
+        assert_eq!(parse_code(r"'\t'"), Ok(Token::Number(9)));
+        assert_eq!(parse_code(r"'\0'"), Ok(Token::Number(0)));
+        assert_eq!(parse_code(r"'\\'"), Ok(Token::Number(92)));
+        assert_eq!(parse_code(r"'\''"), Ok(Token::Number(39)));
+        assert_eq!(parse_code(r"'\x0E'"), Ok(Token::Number(14)));
+        assert_eq!(parse_code(r"'\x7f'"), Ok(Token::Number(127)));
+        assert_eq!(parse_code(r"'\xFF'"), Ok(Token::Number(255)));
+    }
+
+    #[test]
+    fn catches_invalid_literals() {
+        assert_eq!(
+            parse_code("''"),
+            Err(TokenizationError::InvalidCharacterLiteral(Span { start: 0, end: 1, line: 1, column: 1 }))
+        );
+        assert_eq!(
+            parse_code("'ab'"),
+            Err(TokenizationError::InvalidCharacterLiteral(Span { start: 0, end: 2, line: 1, column: 1 }))
+        );
+        assert_eq!(
+            parse_code("'a"),
+            Err(TokenizationError::InvalidCharacterLiteral(Span { start: 0, end: 2, line: 1, column: 1 }))
+        );
+        assert_eq!(
+            parse_code("'€'"),
+            Err(TokenizationError::UnencodableCharacter('€', Span { start: 1, end: 4, line: 1, column: 2 }))
+        );
+        assert_eq!(
+            parse_code(r"'\q'"),
+            Err(TokenizationError::InvalidEscapeSequence(Span { start: 1, end: 3, line: 1, column: 2 }))
+        );
+        assert_eq!(
+            parse_code(r"'\x4'"),
+            Err(TokenizationError::InvalidEscapeSequence(Span { start: 1, end: 4, line: 1, column: 2 }))
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use super::*;
+
+/// Skip a comment if the cursor, just past a `/` at `start`, is at the
+/// second character of one. `//` comments run to the end of the line while
+/// `/* */` comments may span lines and nest.
+///
+/// Returns whether a comment was skipped.
+pub fn skip(char_iter: &mut CharIterator, start: Span) -> Result<bool, TokenizationError> {
+    match char_iter.peek() {
+        Some('/') => {
+            while let Some(&c) = char_iter.peek() {
+                if c == '\n' {
+                    break;
+                }
+                char_iter.next();
+            }
+        },
+        Some('*') => {
+            char_iter.next();
+            let mut depth = 1;
+
+            while depth > 0 {
+                match char_iter.next() {
+                    Some('/') if char_iter.peek() == Some(&'*') => {
+                        char_iter.next();
+                        depth += 1;
+                    },
+                    Some('*') if char_iter.peek() == Some(&'/') => {
+                        char_iter.next();
+                        depth -= 1;
+                    },
+                    Some(_) => (),
+                    None => return Err(TokenizationError::UnterminatedBlockComment(char_iter.span_from(start))),
+                }
+            }
+        },
+        _ => return Ok(false),
+    }
+
+    Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn skip_from_start(code: &str) -> (Result<bool, TokenizationError>, String) {
+        let mut char_iter = CharIterator::new(code);
+        let start = char_iter.location();
+        assert_eq!(char_iter.next(), Some('/'));
+        let result = skip(&mut char_iter, start);
+        (result, char_iter.collect())
+    }
+
+    #[test]
+    fn skips_line_comments() {
+        assert_eq!(skip_from_start("// a /* b\nlet"), (Ok(true), "\nlet".to_string()));
+        assert_eq!(skip_from_start("// to the end"), (Ok(true), "".to_string()));
+    }
+
+    #[test]
+    fn skips_nested_block_comments() {
+        assert_eq!(skip_from_start("/* a /* b */ c */ d"), (Ok(true), " d".to_string()));
+        assert_eq!(skip_from_start("/* a\n// b */c"), (Ok(true), "c".to_string()));
+        assert_eq!(skip_from_start("/**/x"), (Ok(true), "x".to_string()));
+    }
+
+    #[test]
+    fn leaves_division_alone() {
+        assert_eq!(skip_from_start("/ 2"), (Ok(false), " 2".to_string()));
+    }
+
+    #[test]
+    fn catches_unterminated_block_comments() {
+        assert_eq!(
+            skip_from_start("/* a /* b */ c").0,
+            Err(TokenizationError::UnterminatedBlockComment(Span { start: 0, end: 14, line: 1, column: 1 }))
+        );
+        assert_eq!(
+            skip_from_start("/*/").0,
+            Err(TokenizationError::UnterminatedBlockComment(Span { start: 0, end: 3, line: 1, column: 1 }))
+        );
+    }
+}
@@ -0,0 +1,42 @@
+/// Code page 437 from `0x80` up: the characters the PC BIOS draws for those
+/// bytes in text mode. The lower half matches ASCII.
+const UPPER_HALF: [char; 128] = [
+    'Ç', 'ü', 'é', 'â', 'ä', 'à', 'å', 'ç', 'ê', 'ë', 'è', 'ï', 'î', 'ì', 'Ä', 'Å',
+    'É', 'æ', 'Æ', 'ô', 'ö', 'ò', 'û', 'ù', 'ÿ', 'Ö', 'Ü', '¢', '£', '¥', '₧', 'ƒ',
+    'á', 'í', 'ó', 'ú', 'ñ', 'Ñ', 'ª', 'º', '¿', '⌐', '¬', '½', '¼', '¡', '«', '»',
+    '░', '▒', '▓', '│', '┤', '╡', '╢', '╖', '╕', '╣', '║', '╗', '╝', '╜', '╛', '┐',
+    '└', '┴', '┬', '├', '─', '┼', '╞', '╟', '╚', '╔', '╩', '╦', '╠', '═', '╬', '╧',
+    '╨', '╤', '╥', '╙', '╘', '╒', '╓', '╫', '╪', '┘', '┌', '█', '▄', '▌', '▐', '▀',
+    'α', 'ß', 'Γ', 'π', 'Σ', 'σ', 'µ', 'τ', 'Φ', 'Θ', 'Ω', 'δ', '∞', 'φ', 'ε', '∩',
+    '≡', '±', '≥', '≤', '⌠', '⌡', '÷', '≈', '°', '∙', '·', '√', 'ⁿ', '²', '■', '\u{a0}',
+];
+
+/// The byte the BIOS displays as `c`, if there is one
+pub fn encode(c: char) -> Option<u8> {
+    if c.is_ascii() {
+        return Some(c as u8);
+    }
+
+    UPPER_HALF.iter()
+        .position(|&upper| upper == c)
+        .map(|index| 0x80 + index as u8)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn encodes_characters() {
+        assert_eq!(encode('A'), Some(0x41));
+        assert_eq!(encode('\n'), Some(0x0a));
+        assert_eq!(encode('Ç'), Some(0x80));
+        assert_eq!(encode('é'), Some(0x82));
+        assert_eq!(encode('░'), Some(0xb0));
+        assert_eq!(encode('═'), Some(0xcd));
+        assert_eq!(encode('π'), Some(0xe3));
+        assert_eq!(encode('\u{a0}'), Some(0xff));
+        assert_eq!(encode('€'), None);
+        assert_eq!(encode('😀'), None);
+    }
+}
@@ -1,14 +1,66 @@
+use std::fmt;
+use super::diagnostics::Diagnostic;
+use super::span::{ Span, Spanned };
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TokenizationError {
-    UnexpectedCharacter,
-    UnterminatedStringLiteral,
+    UnexpectedCharacter(Span),
+    UnterminatedStringLiteral(Span),
+    UnterminatedBlockComment(Span),
+    InvalidCharacterLiteral(Span),
+    InvalidEscapeSequence(Span),
+    /// The literal as written and where
+    IntegerLiteralOutOfRange(String, Span),
+    /// A character in a literal that code page 437 has no byte for
+    UnencodableCharacter(char, Span),
+}
+
+impl TokenizationError {
+    pub fn span(&self) -> Span {
+        match self {
+            TokenizationError::UnexpectedCharacter(span) => *span,
+            TokenizationError::UnterminatedStringLiteral(span) => *span,
+            TokenizationError::UnterminatedBlockComment(span) => *span,
+            TokenizationError::InvalidCharacterLiteral(span) => *span,
+            TokenizationError::InvalidEscapeSequence(span) => *span,
+            TokenizationError::IntegerLiteralOutOfRange(_, span) => *span,
+            TokenizationError::UnencodableCharacter(_, span) => *span,
+        }
+    }
+
+    pub fn to_diagnostic(&self) -> Diagnostic {
+        let diagnostic = match self {
+            TokenizationError::UnexpectedCharacter(_) => Diagnostic::error("unexpected character"),
+            TokenizationError::UnterminatedStringLiteral(_) => Diagnostic::error("unterminated string literal")
+                .with_label("string starts here")
+                .with_note("string literals must be closed with a `\"` before the end of the file"),
+            TokenizationError::UnterminatedBlockComment(_) => Diagnostic::error("unterminated block comment")
+                .with_label("comment starts here")
+                .with_note("every `/*` needs a matching `*/`, including nested ones"),
+            TokenizationError::InvalidCharacterLiteral(_) => Diagnostic::error("invalid character literal")
+                .with_note("character literals hold exactly one character or escape, like `'A'` or `'\\n'`"),
+            TokenizationError::IntegerLiteralOutOfRange(literal, _) => Diagnostic::error(format!("integer literal `{}` is out of range", literal))
+                .with_label("doesn't fit in 16 bits")
+                .with_note("literals go up to 65535; those above 32767 are unsigned"),
+            TokenizationError::UnencodableCharacter(c, _) => Diagnostic::error(format!("character `{}` can't be displayed by the BIOS", c))
+                .with_label("not in code page 437")
+                .with_note("text is shown using code page 437; use a `\\xHH` escape for any other byte"),
+            TokenizationError::InvalidEscapeSequence(_) => Diagnostic::error("unknown escape sequence")
+                .with_note("supported escapes are `\\n`, `\\r`, `\\t`, `\\0`, `\\\\`, `\\'`, `\\\"` and `\\xHH`"),
+        };
+
+        diagnostic.with_span(self.span())
+    }
 }
 
 #[derive(Debug, PartialEq, Eq, Clone)]
 pub enum Token {
     Semicolon,
-    Number(i16),
-    QuotedString(String),
+    Comma,
+    Number(u16),
+    /// The bytes of the string, with escapes decoded and text encoded to
+    /// code page 437
+    QuotedString(Vec<u8>),
     Identifier(String),
     OpenBrace,
     CloseBrace,
@@ -18,28 +70,285 @@ pub enum Token {
     CloseBracket,
     Equals,
     While,
+    For,
+    Break,
+    Continue,
+    If,
+    Else,
+    Let,
+    Function,
+    Return,
+    Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
+    DoesNotEqual,
+    IsEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Bang,
+    DoubleAmpersand,
+    DoublePipe,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
+}
+
+/// What kind of token a `Token` is, without any value it carries, for
+/// naming the tokens the parser expects
+#[derive(Debug, PartialEq, Eq, Clone, Copy)]
+pub enum TokenKind {
+    Semicolon,
+    Comma,
+    Number,
+    QuotedString,
+    Identifier,
+    OpenBrace,
+    CloseBrace,
+    OpenParen,
+    CloseParen,
+    OpenBracket,
+    CloseBracket,
+    Equals,
+    While,
+    For,
+    Break,
+    Continue,
+    If,
+    Else,
     Let,
     Function,
+    Return,
     Plus,
+    Minus,
+    Star,
+    Slash,
+    Percent,
     DoesNotEqual,
+    IsEqual,
+    LessThan,
+    LessThanOrEqual,
+    GreaterThan,
+    GreaterThanOrEqual,
+    Bang,
+    DoubleAmpersand,
+    DoublePipe,
+    Ampersand,
+    Pipe,
+    Caret,
+    Tilde,
+    ShiftLeft,
+    ShiftRight,
 }
 
-type CharIterator<'a> = std::iter::Peekable<std::str::Chars<'a>>;
+impl TokenKind {
+    /// How a token of this kind is written, for use in messages such as
+    /// "expected `)`"
+    pub fn describe(self) -> &'static str {
+        match self {
+            TokenKind::Semicolon => "`;`",
+            TokenKind::Comma => "`,`",
+            TokenKind::Number => "number",
+            TokenKind::QuotedString => "string literal",
+            TokenKind::Identifier => "identifier",
+            TokenKind::OpenBrace => "`{`",
+            TokenKind::CloseBrace => "`}`",
+            TokenKind::OpenParen => "`(`",
+            TokenKind::CloseParen => "`)`",
+            TokenKind::OpenBracket => "`[`",
+            TokenKind::CloseBracket => "`]`",
+            TokenKind::Equals => "`=`",
+            TokenKind::While => "`while`",
+            TokenKind::For => "`for`",
+            TokenKind::Break => "`break`",
+            TokenKind::Continue => "`continue`",
+            TokenKind::If => "`if`",
+            TokenKind::Else => "`else`",
+            TokenKind::Let => "`let`",
+            TokenKind::Function => "`fn`",
+            TokenKind::Return => "`return`",
+            TokenKind::Plus => "`+`",
+            TokenKind::Minus => "`-`",
+            TokenKind::Star => "`*`",
+            TokenKind::Slash => "`/`",
+            TokenKind::Percent => "`%`",
+            TokenKind::DoesNotEqual => "`!=`",
+            TokenKind::IsEqual => "`==`",
+            TokenKind::LessThan => "`<`",
+            TokenKind::LessThanOrEqual => "`<=`",
+            TokenKind::GreaterThan => "`>`",
+            TokenKind::GreaterThanOrEqual => "`>=`",
+            TokenKind::Bang => "`!`",
+            TokenKind::DoubleAmpersand => "`&&`",
+            TokenKind::DoublePipe => "`||`",
+            TokenKind::Ampersand => "`&`",
+            TokenKind::Pipe => "`|`",
+            TokenKind::Caret => "`^`",
+            TokenKind::Tilde => "`~`",
+            TokenKind::ShiftLeft => "`<<`",
+            TokenKind::ShiftRight => "`>>`",
+        }
+    }
+}
+
+impl Token {
+    pub fn kind(&self) -> TokenKind {
+        match self {
+            Token::Semicolon => TokenKind::Semicolon,
+            Token::Comma => TokenKind::Comma,
+            Token::Number(_) => TokenKind::Number,
+            Token::QuotedString(_) => TokenKind::QuotedString,
+            Token::Identifier(_) => TokenKind::Identifier,
+            Token::OpenBrace => TokenKind::OpenBrace,
+            Token::CloseBrace => TokenKind::CloseBrace,
+            Token::OpenParen => TokenKind::OpenParen,
+            Token::CloseParen => TokenKind::CloseParen,
+            Token::OpenBracket => TokenKind::OpenBracket,
+            Token::CloseBracket => TokenKind::CloseBracket,
+            Token::Equals => TokenKind::Equals,
+            Token::While => TokenKind::While,
+            Token::For => TokenKind::For,
+            Token::Break => TokenKind::Break,
+            Token::Continue => TokenKind::Continue,
+            Token::If => TokenKind::If,
+            Token::Else => TokenKind::Else,
+            Token::Let => TokenKind::Let,
+            Token::Function => TokenKind::Function,
+            Token::Return => TokenKind::Return,
+            Token::Plus => TokenKind::Plus,
+            Token::Minus => TokenKind::Minus,
+            Token::Star => TokenKind::Star,
+            Token::Slash => TokenKind::Slash,
+            Token::Percent => TokenKind::Percent,
+            Token::DoesNotEqual => TokenKind::DoesNotEqual,
+            Token::IsEqual => TokenKind::IsEqual,
+            Token::LessThan => TokenKind::LessThan,
+            Token::LessThanOrEqual => TokenKind::LessThanOrEqual,
+            Token::GreaterThan => TokenKind::GreaterThan,
+            Token::GreaterThanOrEqual => TokenKind::GreaterThanOrEqual,
+            Token::Bang => TokenKind::Bang,
+            Token::DoubleAmpersand => TokenKind::DoubleAmpersand,
+            Token::DoublePipe => TokenKind::DoublePipe,
+            Token::Ampersand => TokenKind::Ampersand,
+            Token::Pipe => TokenKind::Pipe,
+            Token::Caret => TokenKind::Caret,
+            Token::Tilde => TokenKind::Tilde,
+            Token::ShiftLeft => TokenKind::ShiftLeft,
+            Token::ShiftRight => TokenKind::ShiftRight,
+        }
+    }
+
+    pub fn describe(&self) -> &'static str {
+        self.kind().describe()
+    }
+}
+
+impl fmt::Display for Token {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Token::Number(num) => write!(f, "`{}`", num),
+            Token::Identifier(name) => write!(f, "identifier `{}`", name),
+            _ => write!(f, "{}", self.describe()),
+        }
+    }
+}
+
+/// A peekable iterator over the characters of the source that keeps track of
+/// where in the file it currently is.
+pub struct CharIterator<'a> {
+    chars: std::iter::Peekable<std::str::Chars<'a>>,
+    offset: usize,
+    line: usize,
+    column: usize,
+}
+
+impl<'a> CharIterator<'a> {
+    pub fn new(source: &'a str) -> CharIterator<'a> {
+        CharIterator {
+            chars: source.chars().peekable(),
+            offset: 0,
+            line: 1,
+            column: 1,
+        }
+    }
+
+    pub fn peek(&mut self) -> Option<&char> {
+        self.chars.peek()
+    }
+
+    /// An empty span sitting at the cursor's current position
+    pub fn location(&self) -> Span {
+        Span {
+            start: self.offset,
+            end: self.offset,
+            line: self.line,
+            column: self.column,
+        }
+    }
+
+    /// The span between `start` and the cursor's current position
+    pub fn span_from(&self, start: Span) -> Span {
+        Span {
+            end: self.offset,
+            ..start
+        }
+    }
+}
+
+impl Iterator for CharIterator<'_> {
+    type Item = char;
+
+    fn next(&mut self) -> Option<char> {
+        let c = self.chars.next()?;
+        self.offset += c.len_utf8();
+        if c == '\n' {
+            self.line += 1;
+            self.column = 1;
+        } else {
+            self.column += 1;
+        }
+        Some(c)
+    }
+}
 
 fn one_char_token(token: Token, char_iter: &mut CharIterator) -> Token {
     char_iter.next();
     token
 }
 
+/// Produce the two character operator from `longer` whose second character
+/// comes next, or `short` if there's none, for operators such as `<` and `<=`
+/// that share their first character
+fn operator_token<const N: usize>(short: Token, longer: [(char, Token); N], char_iter: &mut CharIterator) -> Token {
+    char_iter.next();
+    for (second, long) in longer {
+        if char_iter.peek() == Some(&second) {
+            char_iter.next();
+            return long;
+        }
+    }
+    short
+}
+
+mod comment;
+mod char_literal;
+mod cp437;
 mod string_literal;
 mod alphanumeric;
 
-pub fn tokenize(contents: String) -> Result<Vec<Token>, TokenizationError> {
-    let mut tokens = Vec::<Token>::new();
-    let mut char_iter = contents.chars().peekable();
+pub fn tokenize(contents: String) -> Result<Vec<Spanned<Token>>, TokenizationError> {
+    let mut tokens = Vec::<Spanned<Token>>::new();
+    let mut char_iter = CharIterator::new(&contents);
 
     while let Some(&c) = char_iter.peek() {
-        tokens.push(match c {
+        let start = char_iter.location();
+        let token = match c {
             '(' => one_char_token(Token::OpenParen, &mut char_iter),
             ')' => one_char_token(Token::CloseParen, &mut char_iter),
             '{' => one_char_token(Token::OpenBrace, &mut char_iter),
@@ -47,22 +356,42 @@ pub fn tokenize(contents: String) -> Result<Vec<Token>, TokenizationError> {
             '[' => one_char_token(Token::OpenBracket, &mut char_iter),
             ']' => one_char_token(Token::CloseBracket, &mut char_iter),
             ';' => one_char_token(Token::Semicolon, &mut char_iter),
-            '=' => one_char_token(Token::Equals, &mut char_iter),
+            ',' => one_char_token(Token::Comma, &mut char_iter),
+            '=' => operator_token(Token::Equals, [('=', Token::IsEqual)], &mut char_iter),
+            '!' => operator_token(Token::Bang, [('=', Token::DoesNotEqual)], &mut char_iter),
+            '<' => operator_token(Token::LessThan, [('=', Token::LessThanOrEqual), ('<', Token::ShiftLeft)], &mut char_iter),
+            '>' => operator_token(Token::GreaterThan, [('=', Token::GreaterThanOrEqual), ('>', Token::ShiftRight)], &mut char_iter),
+            '&' => operator_token(Token::Ampersand, [('&', Token::DoubleAmpersand)], &mut char_iter),
+            '|' => operator_token(Token::Pipe, [('|', Token::DoublePipe)], &mut char_iter),
+            '^' => one_char_token(Token::Caret, &mut char_iter),
+            '~' => one_char_token(Token::Tilde, &mut char_iter),
             '+' => one_char_token(Token::Plus, &mut char_iter),
-            '!' => {
+            '-' => one_char_token(Token::Minus, &mut char_iter),
+            '*' => one_char_token(Token::Star, &mut char_iter),
+            '/' => {
                 char_iter.next();
-                match char_iter.next() {
-                    Some('=') => Token::DoesNotEqual,
-                    _ => return Err(TokenizationError::UnexpectedCharacter)
+                if comment::skip(&mut char_iter, start)? {
+                    continue;
                 }
+                Token::Slash
             },
+            '%' => one_char_token(Token::Percent, &mut char_iter),
             '"' => string_literal::parse(&mut char_iter)?,
+            '\'' => char_literal::parse(&mut char_iter)?,
             c if alphanumeric::is_alphanumeric(c) => alphanumeric::parse(&mut char_iter)?,
             c if c.is_whitespace() => {
                 char_iter.next();
                 continue;
             },
-            _ => return Err(TokenizationError::UnexpectedCharacter)
+            _ => {
+                char_iter.next();
+                return Err(TokenizationError::UnexpectedCharacter(char_iter.span_from(start)))
+            }
+        };
+
+        tokens.push(Spanned {
+            node: token,
+            span: char_iter.span_from(start),
         });
     }
 
@@ -73,6 +402,11 @@ pub fn tokenize(contents: String) -> Result<Vec<Token>, TokenizationError> {
 mod tests {
     use super::*;
 
+    /// The tokens of `code` without their spans
+    fn token_kinds(code: &str) -> Result<Vec<Token>, TokenizationError> {
+        tokenize(String::from(code)).map(|tokens| tokens.into_iter().map(|t| t.node).collect())
+    }
+
     #[test]
     fn tokenizes_codeblock() {
         let code = r#"
@@ -88,9 +422,9 @@ mod tests {
         "#;
 
         assert_eq!(
-            tokenize(String::from(code)),
+            token_kinds(code),
             Ok(vec![
-                Token::Let, Token::Identifier("hello_world".to_string()), Token::Equals, Token::QuotedString("Hello, World!".to_string()), Token::Semicolon,
+                Token::Let, Token::Identifier("hello_world".to_string()), Token::Equals, Token::QuotedString(b"Hello, World!".to_vec()), Token::Semicolon,
                 Token::Function, Token::Identifier("main".to_string()), Token::OpenParen, Token::CloseParen, Token::OpenBrace,
                 Token::Let, Token::Identifier("i".to_string()), Token::Equals, Token::Number(0), Token::Semicolon,
                 Token::While, Token::OpenParen, Token::Identifier("hello_world".to_string()), Token::OpenBracket, Token::Identifier("i".to_string()), Token::CloseBracket, Token::DoesNotEqual, Token::Number(0), Token::CloseParen, Token::OpenBrace,
@@ -101,4 +435,105 @@ mod tests {
             ])
         );
     }
+
+    #[test]
+    fn tokenizes_arithmetic_operators() {
+        assert_eq!(
+            token_kinds("a-b*-c/d%e"),
+            Ok(vec![
+                Token::Identifier("a".to_string()), Token::Minus, Token::Identifier("b".to_string()), Token::Star,
+                Token::Minus, Token::Identifier("c".to_string()), Token::Slash, Token::Identifier("d".to_string()),
+                Token::Percent, Token::Identifier("e".to_string()),
+            ])
+        );
+    }
+
+    #[test]
+    fn tokenizes_comparison_operators() {
+        assert_eq!(
+            token_kinds("= == != < <= > >= =<="),
+            Ok(vec![
+                Token::Equals, Token::IsEqual, Token::DoesNotEqual, Token::LessThan, Token::LessThanOrEqual,
+                Token::GreaterThan, Token::GreaterThanOrEqual, Token::Equals, Token::LessThanOrEqual,
+            ])
+        );
+    }
+
+    #[test]
+    fn tokenizes_logical_and_bitwise_operators() {
+        assert_eq!(
+            token_kinds("!a && b || c & d | e ^ ~f << 1 >> 2 !!= &&& |||"),
+            Ok(vec![
+                Token::Bang, Token::Identifier("a".to_string()), Token::DoubleAmpersand, Token::Identifier("b".to_string()),
+                Token::DoublePipe, Token::Identifier("c".to_string()), Token::Ampersand, Token::Identifier("d".to_string()),
+                Token::Pipe, Token::Identifier("e".to_string()), Token::Caret, Token::Tilde, Token::Identifier("f".to_string()),
+                Token::ShiftLeft, Token::Number(1), Token::ShiftRight, Token::Number(2),
+                Token::Bang, Token::DoesNotEqual, Token::DoubleAmpersand, Token::Ampersand, Token::DoublePipe, Token::Pipe,
+            ])
+        );
+    }
+
+    #[test]
+    fn skips_comments() {
+        let code = "let a = 4 / 2; // halve\n/* block /* nested */\n */ let b = a;";
+        assert_eq!(
+            token_kinds(code),
+            Ok(vec![
+                Token::Let, Token::Identifier("a".to_string()), Token::Equals, Token::Number(4), Token::Slash, Token::Number(2), Token::Semicolon,
+                Token::Let, Token::Identifier("b".to_string()), Token::Equals, Token::Identifier("a".to_string()), Token::Semicolon,
+            ])
+        );
+        assert_eq!(
+            tokenize(String::from("let a = 1;\n/* oops")),
+            Err(TokenizationError::UnterminatedBlockComment(Span { start: 11, end: 18, line: 2, column: 1 }))
+        );
+    }
+
+    #[test]
+    fn tokenizes_number_and_character_literals() {
+        assert_eq!(
+            token_kinds("print('A' + 0x0E - 0b1_0 * '\\n');"),
+            Ok(vec![
+                Token::Identifier("print".to_string()), Token::OpenParen,
+                Token::Number(65), Token::Plus, Token::Number(14), Token::Minus, Token::Number(2), Token::Star, Token::Number(10),
+                Token::CloseParen, Token::Semicolon,
+            ])
+        );
+    }
+
+    #[test]
+    fn describes_tokens() {
+        assert_eq!(Token::CloseParen.to_string(), "`)`");
+        assert_eq!(Token::Function.to_string(), "`fn`");
+        assert_eq!(Token::Number(42).to_string(), "`42`");
+        assert_eq!(Token::Identifier("foo".to_string()).to_string(), "identifier `foo`");
+        assert_eq!(TokenKind::Identifier.describe(), "identifier");
+    }
+
+    #[test]
+    fn records_token_spans() {
+        let code = "let s = \"é\";\n  s != 10";
+
+        assert_eq!(
+            tokenize(String::from(code)).map(|tokens| tokens.into_iter().map(|t| t.span).collect::<Vec<_>>()),
+            Ok(vec![
+                Span { start: 0, end: 3, line: 1, column: 1 },
+                Span { start: 4, end: 5, line: 1, column: 5 },
+                Span { start: 6, end: 7, line: 1, column: 7 },
+                Span { start: 8, end: 12, line: 1, column: 9 },
+                Span { start: 12, end: 13, line: 1, column: 12 },
+                Span { start: 16, end: 17, line: 2, column: 3 },
+                Span { start: 18, end: 20, line: 2, column: 5 },
+                Span { start: 21, end: 23, line: 2, column: 8 },
+            ])
+        );
+    }
+
+    #[test]
+    fn reports_error_positions() {
+        assert_eq!(
+            tokenize(String::from("let a = 1;\nlet b = a $ 2;")),
+            Err(TokenizationError::UnexpectedCharacter(Span { start: 21, end: 22, line: 2, column: 11 }))
+        );
+    }
 }
@@ -1,18 +1,21 @@
 use super::*;
 
 pub fn parse(char_iter: &mut CharIterator) -> Result<Token, TokenizationError> {
-    let mut literal = String::new();
+    let start = char_iter.location();
+    let mut literal = vec![];
 
     // Skip the opening quote
     assert_eq!(char_iter.next(), Some('"'));
 
     loop {
-        match char_iter.next() {
-            Some('"') => break,
-            Some(c) => {
-                literal.push(c);
-            },
-            None => return Err(TokenizationError::UnterminatedStringLiteral)
+        if char_iter.peek() == Some(&'"') {
+            char_iter.next();
+            break;
+        }
+
+        match char_literal::parse_byte(char_iter)? {
+            Some(byte) => literal.push(byte),
+            None => return Err(TokenizationError::UnterminatedStringLiteral(char_iter.span_from(start)))
         }
     }
 
@@ -27,8 +30,29 @@ mod tests {
     fn extract_string_literal() {
         let code = r#""FooBar""#;
         assert_eq!(
-            parse(&mut code.chars().peekable()),
-            Ok(Token::QuotedString(String::from("FooBar")))
+            parse(&mut CharIterator::new(code)),
+            Ok(Token::QuotedString(b"FooBar".to_vec()))
+        );
+    }
+
+    #[test]
+    fn decodes_escapes() {
+        let code = r#""say \"hi\"\\\n\x41\0""#;
+        assert_eq!(
+            parse(&mut CharIterator::new(code)),
+            Ok(Token::QuotedString(b"say \"hi\"\\\nA\0".to_vec()))
+        );
+    }
+
+    #[test]
+    fn encodes_text_to_code_page_437() {
+        assert_eq!(
+            parse(&mut CharIterator::new("\"café ░\"")),
+            Ok(Token::QuotedString(vec![b'c', b'a', b'f', 0x82, b' ', 0xb0]))
+        );
+        assert_eq!(
+            parse(&mut CharIterator::new("\"5€\"")),
+            Err(TokenizationError::UnencodableCharacter('€', Span { start: 2, end: 5, line: 1, column: 3 }))
         );
     }
 
@@ -36,8 +60,12 @@ mod tests {
     fn catches_unclosed_string_literal() {
         let code = r#""FooBar"#;
         assert_eq!(
-            parse(&mut code.chars().peekable()),
-            Err(TokenizationError::UnterminatedStringLiteral)
+            parse(&mut CharIterator::new(code)),
+            Err(TokenizationError::UnterminatedStringLiteral(Span { start: 0, end: 7, line: 1, column: 1 }))
+        );
+        assert_eq!(
+            parse(&mut CharIterator::new(r#""ends in \""#)),
+            Err(TokenizationError::UnterminatedStringLiteral(Span { start: 0, end: 11, line: 1, column: 1 }))
         );
     }
 }